@@ -1,56 +1,1224 @@
 // lib.rs - Extract functions for testing
 use chrono::{Datelike, NaiveDate, NaiveDateTime, DateTime, Local, Duration, Timelike, FixedOffset, TimeZone};
 use regex::Regex;
+use encoding_rs::{Encoding, SHIFT_JIS, UTF_16BE, UTF_16LE};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
 use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
     fs,
+    io::{self, Write},
     path::{PathBuf, Path},
 };
 use walkdir::WalkDir;
 use std::collections::hash_map::DefaultHasher;
 use std::hash::{Hash, Hasher};
 
+pub mod schema;
+pub mod xlsx;
+
+pub use schema::{
+    diff_schemas, parse_csv_schema, read_csv_schema, schema_diffs, CsvSchema, SchemaDiff,
+    SchemaDrift, SchemaDriftKind, DEFAULT_ROW_JUMP_PERCENT,
+};
+pub use xlsx::{
+    diff_workbooks, inspect_workbooks, inspect_xlsx, workbook_diffs, SheetInfo, WorkbookChange,
+    WorkbookChangeKind, WorkbookDiff, WorkbookInfo, WorkbookSnapshot,
+};
+
 #[derive(Debug, Clone)]
 pub struct FileInfo {
     pub actual_name: String,
     pub size: u64,
     pub created: String,      // "YYYY/MM/DD HH:MM"
     pub modified: String,     // "YYYY/MM/DD HH:MM"
-    pub date_str: String,     // "YYYY-MM"
+    pub date_str: String,     // "YYYY-MM", "YYYY-MM-DD" or "YYYY-Www" (see Granularity)
     /// Path relative to the resolved monthly root (e.g. "Sub/InTheBox08-2024.xlsx")
     pub rel_path: String,
-    /// Relative path where yyyy/mm are normalized to {yyyy}/{mm} on the file name part
+    /// Relative path where yyyy/mm are normalized to {yyyy}/{mm} on the file name part (and
+    /// the directories selected by `CollectOptions::dir_depths`), then rewritten by the user
+    /// normalization rules
+    pub normalized_rel_path: String,
+    /// Hex digest of the file content, when hashing is enabled
+    pub content_hash: Option<String>,
+    /// Date shape recognised in the file name (e.g. "{mm}-{yyyy}"), see `DATE_SHAPES`
+    pub date_shape: Option<String>,
+    /// Months between the date in the file name and the folder date (-1 for a previous
+    /// month's file), see `CollectOptions::month_window`
+    pub date_offset: Option<i32>,
+}
+
+/// Content hash used to tell real edits from touched or same-size rewrites.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HashAlgorithm {
+    Sha256,
+    /// XXH3-64: much faster, not collision resistant against adversaries
+    Xxh3,
+}
+
+impl std::str::FromStr for HashAlgorithm {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "sha256" => Ok(HashAlgorithm::Sha256),
+            "xxh3" => Ok(HashAlgorithm::Xxh3),
+            other => Err(format!("unknown hash algorithm '{}' (expected sha256 or xxh3)", other)),
+        }
+    }
+}
+
+/// How often snapshot folders are taken. Drives date discovery, `date_str` labels and
+/// whether `{dd}` is normalized in file names.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Granularity {
+    Daily,
+    /// One snapshot per ISO week
+    Weekly,
+    #[default]
+    Monthly,
+}
+
+impl std::str::FromStr for Granularity {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "daily" => Ok(Granularity::Daily),
+            "weekly" => Ok(Granularity::Weekly),
+            "monthly" => Ok(Granularity::Monthly),
+            other => Err(format!(
+                "unknown granularity '{}' (expected daily, weekly or monthly)",
+                other
+            )),
+        }
+    }
+}
+
+impl Granularity {
+    /// Period label of a snapshot date: "YYYY-MM-DD", ISO week "YYYY-Www" or "YYYY-MM".
+    /// Labels sort chronologically as strings.
+    pub fn label(&self, date: NaiveDate) -> String {
+        match self {
+            Granularity::Daily => date.format("%Y-%m-%d").to_string(),
+            Granularity::Weekly => date.format("%G-W%V").to_string(),
+            Granularity::Monthly => date.format("%Y-%m").to_string(),
+        }
+    }
+}
+
+/// Expected snapshot dates from `from` to `to` (inclusive), one per period: every day,
+/// every 7 days from `from`, or the 1st of every month.
+pub fn date_range(from: NaiveDate, to: NaiveDate, granularity: Granularity) -> Vec<NaiveDate> {
+    let mut dates = Vec::new();
+    let mut current = match granularity {
+        Granularity::Monthly => from.with_day(1).unwrap(),
+        Granularity::Daily | Granularity::Weekly => from,
+    };
+    while current <= to {
+        dates.push(current);
+        current = match granularity {
+            Granularity::Daily => current + Duration::days(1),
+            Granularity::Weekly => current + Duration::weeks(1),
+            Granularity::Monthly => match current.checked_add_months(chrono::Months::new(1)) {
+                Some(next) => next,
+                None => break,
+            },
+        };
+    }
+    dates
+}
+
+/// The last `count` expected snapshot dates, ending with the period containing `end`.
+pub fn last_periods(end: NaiveDate, count: usize, granularity: Granularity) -> Vec<NaiveDate> {
+    if count == 0 {
+        return Vec::new();
+    }
+    let back = (count - 1) as u32;
+    let from = match granularity {
+        Granularity::Daily => end - Duration::days(back as i64),
+        Granularity::Weekly => end - Duration::weeks(back as i64),
+        Granularity::Monthly => end
+            .with_day(1)
+            .unwrap()
+            .checked_sub_months(chrono::Months::new(back))
+            .unwrap_or(NaiveDate::MIN),
+    };
+    date_range(from, end, granularity)
+}
+
+/// First month of the fiscal year used when none is configured (April).
+pub const DEFAULT_FISCAL_START_MONTH: u32 = 4;
+
+/// Fiscal year and quarter (1-4) of a date. A fiscal year is named after the calendar
+/// year it starts in, so with an April start FY2024 runs from 2024-04 to 2025-03.
+pub fn fiscal_year_quarter(date: NaiveDate, fiscal_start_month: u32) -> (i32, u32) {
+    let fy = if date.month() >= fiscal_start_month {
+        date.year()
+    } else {
+        date.year() - 1
+    };
+    let months_in = (date.month() + 12 - fiscal_start_month) % 12;
+    (fy, months_in / 3 + 1)
+}
+
+/// First day of a fiscal quarter.
+pub fn fiscal_quarter_start(fiscal_year: i32, quarter: u32, fiscal_start_month: u32) -> Option<NaiveDate> {
+    let month0 = fiscal_start_month - 1 + (quarter.checked_sub(1)?) * 3;
+    NaiveDate::from_ymd_opt(fiscal_year + (month0 / 12) as i32, month0 % 12 + 1, 1)
+}
+
+/// Coarser period that snapshots are rolled up to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rollup {
+    /// Fiscal quarter, labelled "FY2024-Q1"
+    Quarter,
+    /// Fiscal year, labelled "FY2024"
+    FiscalYear,
+}
+
+impl std::str::FromStr for Rollup {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "quarter" => Ok(Rollup::Quarter),
+            "fiscal-year" | "fy" => Ok(Rollup::FiscalYear),
+            other => Err(format!("unknown rollup '{}' (expected quarter or fiscal-year)", other)),
+        }
+    }
+}
+
+impl Rollup {
+    pub fn label(&self, date: NaiveDate, fiscal_start_month: u32) -> String {
+        let (fy, q) = fiscal_year_quarter(date, fiscal_start_month);
+        match self {
+            Rollup::Quarter => format!("FY{}-Q{}", fy, q),
+            Rollup::FiscalYear => format!("FY{}", fy),
+        }
+    }
+}
+
+/// How to choose among several folders matching a `*`/`?` template segment.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MatchPolicy {
+    /// Most recently modified folder
+    #[default]
+    LatestMtime,
+    /// Last folder name in lexical order
+    LexicalLast,
+    /// Refuse to choose
+    Error,
+}
+
+impl std::str::FromStr for MatchPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "latest-mtime" => Ok(MatchPolicy::LatestMtime),
+            "lexical-last" => Ok(MatchPolicy::LexicalLast),
+            "error" => Ok(MatchPolicy::Error),
+            other => Err(format!(
+                "unknown match policy '{}' (expected latest-mtime, lexical-last or error)",
+                other
+            )),
+        }
+    }
+}
+
+/// Keep the latest date of each rolled-up period, so every quarter or fiscal year is
+/// represented by its end-of-period snapshot.
+pub fn roll_up_dates(dates: &[NaiveDate], rollup: Rollup, fiscal_start_month: u32) -> Vec<NaiveDate> {
+    let mut sorted = dates.to_vec();
+    sorted.sort_unstable();
+    let mut latest: Vec<NaiveDate> = Vec::new();
+    for d in sorted {
+        match latest.last_mut() {
+            Some(prev) if rollup.label(*prev, fiscal_start_month) == rollup.label(d, fiscal_start_month) => {
+                *prev = d
+            }
+            _ => latest.push(d),
+        }
+    }
+    latest
+}
+
+/// Options for `collect_files_with`.
+#[derive(Debug, Clone)]
+pub struct CollectOptions {
+    pub max_depth: usize,
+    pub detect_filename_dates: bool,
+    pub hash: Option<HashAlgorithm>,
+    pub granularity: Granularity,
+    /// Label snapshots by fiscal quarter or year instead of by granularity
+    pub rollup: Option<Rollup>,
+    pub fiscal_start_month: u32,
+    /// Applied in order after the date normalization, see `parse_normalization_rules`
+    pub rules: Vec<NormalizationRule>,
+    /// Directory depths (1 = directly below the root) whose names are date-normalized too
+    pub dir_depths: Vec<usize>,
+    /// Also recognise file name dates up to this many months before or after the folder
+    /// date (0 = the folder's own month only)
+    pub month_window: u32,
+}
+
+impl Default for CollectOptions {
+    fn default() -> Self {
+        Self {
+            max_depth: 2,
+            detect_filename_dates: true,
+            hash: None,
+            granularity: Granularity::Monthly,
+            rollup: None,
+            fiscal_start_month: DEFAULT_FISCAL_START_MONTH,
+            rules: Vec::new(),
+            dir_depths: Vec::new(),
+            month_window: 0,
+        }
+    }
+}
+
+impl CollectOptions {
+    /// `date_str` label of a snapshot date.
+    pub fn period_label(&self, date: NaiveDate) -> String {
+        match self.rollup {
+            Some(rollup) => rollup.label(date, self.fiscal_start_month),
+            None => self.granularity.label(date),
+        }
+    }
+}
+
+/// Options for `extract_dates_from_template_with`.
+#[derive(Debug, Clone)]
+pub struct DiscoverOptions {
+    pub granularity: Granularity,
+    /// Resolves `{fy}`/`{q}` folders to the first month of the fiscal period
+    pub fiscal_start_month: u32,
+    /// Match each placeholder segment against the whole folder name
+    pub strict: bool,
+    /// How `resolve_path` chooses among `*`/`?` matches; folders it would choose between are
+    /// only reported as ambiguous with `MatchPolicy::Error`
+    pub match_policy: MatchPolicy,
+}
+
+impl Default for DiscoverOptions {
+    fn default() -> Self {
+        Self {
+            granularity: Granularity::Monthly,
+            fiscal_start_month: DEFAULT_FISCAL_START_MONTH,
+            strict: false,
+            match_policy: MatchPolicy::default(),
+        }
+    }
+}
+
+/// All files collected for one resolved period (one template date).
+#[derive(Debug, Clone)]
+pub struct Snapshot {
+    pub date_str: String, // period label, see Granularity
+    /// Resolved folder the files were collected from
+    pub root: PathBuf,
+    pub files: Vec<FileInfo>,
+}
+
+/// How a normalized path changed between two consecutive snapshots.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChangeKind {
+    Added,
+    Removed,
+    SizeChanged,
+    /// Same size but different content hash
+    ContentChanged,
+    MtimeChanged,
+    Unchanged,
+    /// Removed + added pair matched by content hash, or by size and name similarity
+    Renamed,
+}
+
+impl ChangeKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ChangeKind::Added => "added",
+            ChangeKind::Removed => "removed",
+            ChangeKind::SizeChanged => "size_changed",
+            ChangeKind::ContentChanged => "content_changed",
+            ChangeKind::MtimeChanged => "mtime_changed",
+            ChangeKind::Unchanged => "unchanged",
+            ChangeKind::Renamed => "renamed",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct DiffEntry {
     pub normalized_rel_path: String,
+    pub prev_date_str: String,
+    pub date_str: String,
+    pub kind: ChangeKind,
+    /// File in the previous snapshot (None when added)
+    pub previous: Option<FileInfo>,
+    /// File in the current snapshot (None when removed)
+    pub current: Option<FileInfo>,
+    /// Previous normalized path when the file was renamed or moved
+    pub renamed_from: Option<String>,
+}
+
+impl DiffEntry {
+    /// Size difference in bytes (current - previous), when both sides exist.
+    pub fn size_delta(&self) -> Option<i64> {
+        match (&self.previous, &self.current) {
+            (Some(p), Some(c)) => Some(c.size as i64 - p.size as i64),
+            _ => None,
+        }
+    }
+
+    /// Whether the content differs, when both sides carry a content hash.
+    pub fn content_differs(&self) -> Option<bool> {
+        let p = self.previous.as_ref()?.content_hash.as_ref()?;
+        let c = self.current.as_ref()?.content_hash.as_ref()?;
+        Some(p != c)
+    }
+}
+
+/// English month abbreviations used by `{mmm}`.
+pub const MONTH_ABBR_EN: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+/// Traditional Japanese month names used by `{mon_ja}`.
+pub const MONTH_NAMES_JA: [&str; 12] = [
+    "睦月", "如月", "弥生", "卯月", "皐月", "水無月", "文月", "葉月", "長月", "神無月", "霜月", "師走",
+];
+
+/// A Japanese era (gengo).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Era {
+    pub name: &'static str,
+    /// Romaji initial used in short forms like "R06.12"
+    pub initial: &'static str,
+    /// First day of the era (year, month, day)
+    pub start: (i32, u32, u32),
+}
+
+/// Supported eras, newest first.
+pub const JAPANESE_ERAS: [Era; 3] = [
+    Era { name: "令和", initial: "R", start: (2019, 5, 1) },
+    Era { name: "平成", initial: "H", start: (1989, 1, 8) },
+    Era { name: "昭和", initial: "S", start: (1926, 12, 25) },
+];
+
+/// Era and era year (1 = 元年) of a date, or None before Showa.
+pub fn to_wareki(date: NaiveDate) -> Option<(&'static Era, i32)> {
+    JAPANESE_ERAS.iter().find_map(|era| {
+        let (y, m, d) = era.start;
+        let start = NaiveDate::from_ymd_opt(y, m, d)?;
+        (date >= start).then(|| (era, era_year_of(era, date.year())))
+    })
+}
+
+/// Era spellings of a snapshot date, the date's own era first. In the month an era begins
+/// the neighbouring era is included, since a monthly snapshot is dated the 1st and its folder
+/// may be named either way: 1989-01 as 昭和64年 or 平成元年, 2019-05 as 令和元年 or 平成31年.
+fn wareki_candidates(date: NaiveDate) -> Vec<(&'static Era, i32)> {
+    let mut out: Vec<(&'static Era, i32)> = to_wareki(date).into_iter().collect();
+    for (i, era) in JAPANESE_ERAS.iter().enumerate() {
+        if (era.start.0, era.start.1) != (date.year(), date.month()) {
+            continue;
+        }
+        // The era beginning this month and the one it replaces
+        for e in [Some(era), JAPANESE_ERAS.get(i + 1)].into_iter().flatten() {
+            if !out.iter().any(|(o, _)| *o == e) {
+                out.push((e, era_year_of(e, date.year())));
+            }
+        }
+    }
+    out
+}
+
+/// Era year of a Gregorian year, counting past the era's end like `from_wareki`.
+fn era_year_of(era: &Era, year: i32) -> i32 {
+    year - era.start.0 + 1
+}
+
+/// Gregorian year of an era year. Years past the era's end (e.g. 平成31年 after April 2019)
+/// still convert by counting from the era's first year.
+pub fn from_wareki(era: &Era, era_year: i32) -> i32 {
+    era.start.0 + era_year - 1
+}
+
+/// Accept both `/` and `\` as separators: backslashes become `/` except on Windows,
+/// where both are already separators.
+pub fn normalize_separators(path: &str) -> String {
+    if cfg!(windows) {
+        path.to_string()
+    } else {
+        path.replace('\\', "/")
+    }
+}
+
+/// Rewrites a template prefix such as a drive letter (`D:`) or UNC share (`\\server\share`)
+/// into a local mount point (`/mnt/d`).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PathMapping {
+    pub from: String,
+    pub to: String,
+}
+
+impl std::str::FromStr for PathMapping {
+    type Err = String;
+
+    /// Parse "FROM=TO", e.g. "D:=/mnt/d".
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once('=') {
+            Some((from, to)) if !from.trim().is_empty() => Ok(PathMapping {
+                from: from.trim().to_string(),
+                to: to.trim().to_string(),
+            }),
+            _ => Err(format!("invalid path mapping '{}' (expected FROM=TO, e.g. D:=/mnt/d)", s)),
+        }
+    }
+}
+
+/// Parse a mapping table: one "FROM=TO" per line; blank lines and `#` comments are ignored.
+pub fn parse_path_mappings(text: &str) -> Result<Vec<PathMapping>, String> {
+    text.lines()
+        .map(str::trim)
+        .filter(|l| !l.is_empty() && !l.starts_with('#'))
+        .map(str::parse)
+        .collect()
+}
+
+/// Apply the first mapping whose prefix matches the template. Prefixes compare
+/// case-insensitively with either separator and must end at a path boundary.
+pub fn map_path_prefix(template: &str, mappings: &[PathMapping]) -> String {
+    let unified = template.replace('\\', "/");
+    for mapping in mappings {
+        let from = mapping.from.replace('\\', "/");
+        let from = from.trim_end_matches('/');
+        let head = match unified.get(..from.len()) {
+            Some(head) => head,
+            None => continue,
+        };
+        let rest = &template[from.len()..];
+        if head.eq_ignore_ascii_case(from) && (rest.is_empty() || rest.starts_with(['/', '\\'])) {
+            return format!("{}{}", mapping.to.trim_end_matches(['/', '\\']), rest);
+        }
+    }
+    template.to_string()
+}
+
+/// Expand a leading `~` and `$VAR`, `${VAR}` or `%VAR%` references using the process
+/// environment. Fails on the first undefined variable.
+pub fn expand_env(template: &str) -> Result<String, String> {
+    expand_env_with(template, |name| std::env::var(name).ok())
 }
 
+/// `expand_env` with a custom variable lookup. `~` is the `HOME` (or `USERPROFILE`) variable.
+pub fn expand_env_with(template: &str, lookup: impl Fn(&str) -> Option<String>) -> Result<String, String> {
+    let mut expanded = template.to_string();
+    if expanded == "~" || expanded.starts_with("~/") || expanded.starts_with("~\\") {
+        let home = lookup("HOME")
+            .or_else(|| lookup("USERPROFILE"))
+            .ok_or_else(|| "cannot expand '~': HOME is not set".to_string())?;
+        expanded = format!("{}{}", home, &expanded[1..]);
+    }
+
+    let re = Regex::new(r"\$\{([A-Za-z_][A-Za-z0-9_]*)\}|\$([A-Za-z_][A-Za-z0-9_]*)|%([A-Za-z_][A-Za-z0-9_()]*)%").unwrap();
+    let mut undefined = None;
+    let result = re.replace_all(&expanded, |caps: &regex::Captures| {
+        let name = caps.get(1).or_else(|| caps.get(2)).or_else(|| caps.get(3)).unwrap().as_str();
+        lookup(name).unwrap_or_else(|| {
+            undefined.get_or_insert_with(|| name.to_string());
+            String::new()
+        })
+    });
+    match undefined {
+        Some(name) => Err(format!("undefined environment variable '{}' in template", name)),
+        None => Ok(result.into_owned()),
+    }
+}
+
+/// Replace `{yyyy}`, `{yy}`, `{mm}`, `{m}`, `{mmm}`, `{mon_ja}` and `{dd}` with the date, and
+/// the era placeholders `{gengo}` (令和), `{g}` (R), `{ey}` (6) and `{eyy}` (06).
+/// `{fy}`/`{q}` use an April fiscal start; see `resolve_template_with`.
+/// `*`/`?` wildcards are kept literally; use `resolve_path` to expand them against the
+/// file system with a `MatchPolicy`.
 pub fn resolve_template(path_template: &str, date: NaiveDate) -> PathBuf {
+    resolve_template_with(path_template, date, DEFAULT_FISCAL_START_MONTH)
+}
+
+/// `resolve_template` with the fiscal year (`{fy}`) and quarter (`{q}`) counted from
+/// `fiscal_start_month`. Wildcards are not expanded either. The first year of an era is
+/// written `元` for `{ey}` (令和元年); `resolve_path` also tries `1` and the neighbouring era.
+pub fn resolve_template_with(path_template: &str, date: NaiveDate, fiscal_start_month: u32) -> PathBuf {
+    render_template(path_template, date, fiscal_start_month, to_wareki(date), true)
+}
+
+/// Every spelling of the template for a date, preferred first: each candidate era, with
+/// `{ey}` of a first year as `元` and then as `1`.
+fn template_spellings(path_template: &str, date: NaiveDate, fiscal_start_month: u32) -> Vec<PathBuf> {
+    let eras: Vec<Option<(&Era, i32)>> = match wareki_candidates(date) {
+        candidates if candidates.is_empty() => vec![None],
+        candidates => candidates.into_iter().map(Some).collect(),
+    };
+    let mut out: Vec<PathBuf> = Vec::new();
+    for wareki in eras {
+        for gannen in [true, false] {
+            let path = render_template(path_template, date, fiscal_start_month, wareki, gannen);
+            if !out.contains(&path) {
+                out.push(path);
+            }
+        }
+    }
+    out
+}
+
+fn render_template(
+    path_template: &str,
+    date: NaiveDate,
+    fiscal_start_month: u32,
+    wareki: Option<(&Era, i32)>,
+    gannen: bool,
+) -> PathBuf {
+    let month_index = date.month0() as usize;
+    let (fy, q) = fiscal_year_quarter(date, fiscal_start_month);
+    let mut path_template = path_template
+        .replace("{fy}", &fy.to_string())
+        .replace("{q}", &q.to_string());
+    if let Some((era, ey)) = wareki {
+        let ey_text = if ey == 1 && gannen { "元".to_string() } else { ey.to_string() };
+        path_template = path_template
+            .replace("{gengo}", era.name)
+            .replace("{g}", era.initial)
+            .replace("{eyy}", &format!("{:02}", ey))
+            .replace("{ey}", &ey_text);
+    }
     let replaced = path_template
         .replace("{yyyy}", &format!("{}", date.year()))
+        .replace("{yy}", &format!("{:02}", date.year().rem_euclid(100)))
+        .replace("{mmm}", MONTH_ABBR_EN[month_index])
+        .replace("{mon_ja}", MONTH_NAMES_JA[month_index])
         .replace("{mm}", &format!("{:02}", date.month()))
+        .replace("{m}", &format!("{}", date.month()))
         .replace("{dd}", &format!("{:02}", date.day()));
-    PathBuf::from(replaced)
+    PathBuf::from(normalize_separators(&replaced))
+}
+
+pub fn normalize_filename(name: &str, yyyy: i32, mm: u32) -> String {
+    DateNormalizer::for_month(yyyy, mm, None, 0).normalize(name).normalized
+}
+
+fn has_glob(segment: &str) -> bool {
+    segment.contains('*') || segment.contains('?')
+}
+
+/// Regex source for literal template text, turning `*` and `?` into wildcards.
+fn glob_to_regex(text: &str) -> String {
+    let mut out = String::new();
+    for ch in text.chars() {
+        match ch {
+            '*' => out.push_str(".*"),
+            '?' => out.push('.'),
+            _ => out.push_str(&regex::escape(&ch.to_string())),
+        }
+    }
+    out
+}
+
+/// Resolve the template for a date and expand `*`/`?` segments against the file system,
+/// choosing among several matches with `policy`. Segments without a match are kept as
+/// written, so the returned path simply does not exist.
+/// Era templates take the first existing spelling: `元` or `1` for the first year of an era,
+/// and either era in the month an era begins (see `resolve_template_with`).
+pub fn resolve_path(
+    path_template: &str,
+    date: NaiveDate,
+    fiscal_start_month: u32,
+    policy: MatchPolicy,
+) -> io::Result<PathBuf> {
+    let mut preferred = None;
+    for resolved in template_spellings(path_template, date, fiscal_start_month) {
+        let path = expand_globs(resolved, policy)?;
+        if path.exists() {
+            return Ok(path);
+        }
+        preferred.get_or_insert(path);
+    }
+    Ok(preferred.expect("a template has at least one spelling"))
+}
+
+fn expand_globs(resolved: PathBuf, policy: MatchPolicy) -> io::Result<PathBuf> {
+    if !has_glob(&resolved.to_string_lossy()) {
+        return Ok(resolved);
+    }
+
+    let mut current = PathBuf::new();
+    for comp in resolved.components() {
+        let segment = comp.as_os_str().to_string_lossy().to_string();
+        if !has_glob(&segment) {
+            current.push(comp);
+            continue;
+        }
+        let re = Regex::new(&format!("^{}$", glob_to_regex(&segment))).expect("Invalid regex from template");
+        let dir = if current.as_os_str().is_empty() {
+            PathBuf::from(".")
+        } else {
+            current.clone()
+        };
+        let mut candidates: Vec<PathBuf> = match fs::read_dir(&dir) {
+            Ok(entries) => entries
+                .flatten()
+                .filter(|e| re.is_match(&e.file_name().to_string_lossy()))
+                .map(|e| current.join(e.file_name()))
+                .collect(),
+            Err(_) => Vec::new(),
+        };
+        if candidates.is_empty() {
+            return Ok(resolved);
+        }
+        candidates.sort();
+        current = choose_match(candidates, policy)?;
+    }
+    Ok(current)
+}
+
+fn choose_match(mut candidates: Vec<PathBuf>, policy: MatchPolicy) -> io::Result<PathBuf> {
+    if candidates.len() == 1 {
+        return Ok(candidates.remove(0));
+    }
+    match policy {
+        MatchPolicy::LexicalLast => Ok(candidates.pop().unwrap()),
+        // max_by_key keeps the last maximum, so ties go to the lexically last name
+        MatchPolicy::LatestMtime => Ok(candidates
+            .into_iter()
+            .max_by_key(|p| fs::metadata(p).and_then(|m| m.modified()).ok())
+            .unwrap()),
+        MatchPolicy::Error => Err(io::Error::other(format!(
+            "{} folders match: {}",
+            candidates.len(),
+            candidates.iter().map(|p| p.display().to_string()).collect::<Vec<_>>().join(", ")
+        ))),
+    }
+}
+
+/// Date layouts recognised in file names, most specific first. `-` stands for one of "-",
+/// "_", "." or a space; adjacent tokens have no separator. Only digits that render the
+/// snapshot's own date are replaced, and for monthly snapshots `{dd}` matches any day and is
+/// kept as is.
+pub const DATE_SHAPES: &[&str] = &[
+    "{yyyy}-{mm}-{dd}",
+    "{yyyy}{mm}{dd}",
+    "{yyyy}年{mm}月{dd}日",
+    "{dd}-{mm}-{yyyy}",
+    "{yy}-{mm}-{dd}",
+    "{yy}{mm}{dd}",
+    "{yyyy}年{mm}月",
+    "{yyyy}年{m}月",
+    "{gengo}{ey}年{mm}月",
+    "{gengo}{ey}年{m}月",
+    "{g}{eyy}-{mm}",
+    "{g}{ey}-{mm}",
+    "{yyyy}-{mm}",
+    "{yyyy}{mm}",
+    "{mm}-{yyyy}",
+    "{mmm}-{yyyy}",
+    "{mmm}{yyyy}",
+    "{yyyy}-{mmm}",
+    "{yyyy}{mmm}",
+    "{yyyy}-{m}",
+    "{m}-{yyyy}",
+    "{yy}-{mm}",
+    "{yy}{mm}",
+    "{mm}-{yy}",
+    "{mm}月",
+    "{m}月",
+    "{mmm}",
+    "{mon_ja}",
+    "{gengo}{ey}年",
+    "{yyyy}",
+];
+
+/// Lone zero-padded month and day, only tried when no shape above carried the month.
+const LONE_SHAPES: &[&str] = &["{mm}", "{dd}"];
+
+#[derive(Debug, Clone)]
+enum ShapeToken<'a> {
+    Placeholder(&'a str),
+    Separator,
+    Literal(char),
+}
+
+fn shape_tokens(shape: &str) -> Vec<ShapeToken<'_>> {
+    let mut tokens = Vec::new();
+    let mut rest = shape;
+    while let Some(c) = rest.chars().next() {
+        if c == '{' {
+            if let Some(end) = rest.find('}') {
+                tokens.push(ShapeToken::Placeholder(&rest[1..end]));
+                rest = &rest[end + 1..];
+                continue;
+            }
+        }
+        tokens.push(if c == '-' { ShapeToken::Separator } else { ShapeToken::Literal(c) });
+        rest = &rest[c.len_utf8()..];
+    }
+    tokens
+}
+
+/// Which characters may not touch a token: digits for numbers, ASCII letters for
+/// `{mmm}`/`{g}`, nothing for Japanese text.
+fn token_boundary(token: &ShapeToken) -> Option<fn(char) -> bool> {
+    match token {
+        ShapeToken::Placeholder("mmm" | "g") => Some(|c: char| c.is_ascii_alphabetic()),
+        ShapeToken::Placeholder("gengo" | "mon_ja") => None,
+        ShapeToken::Placeholder(_) => Some(|c: char| c.is_ascii_digit()),
+        _ => None,
+    }
+}
+
+/// Placeholder written for a date `offset` months away from the folder date: month tokens
+/// carry the offset (`{mm-1}`), year tokens the year of that month (`{yyyy-1m}`).
+fn offset_placeholder(name: &str, offset: i32) -> String {
+    match (offset, name) {
+        (0, _) => format!("{{{}}}", name),
+        (_, "mm" | "m" | "mmm" | "mon_ja") => format!("{{{}{:+}}}", name, offset),
+        _ => format!("{{{}{:+}m}}", name, offset),
+    }
+}
+
+/// One `DATE_SHAPES` entry compiled for one date (and era).
+#[derive(Debug, Clone)]
+struct CompiledShape {
+    shape: &'static str,
+    tokens: Vec<ShapeToken<'static>>,
+    re: Regex,
+    before: Option<fn(char) -> bool>,
+    after: Option<fn(char) -> bool>,
+    /// `{dd}` matches any day and is kept as is
+    keep_day: bool,
+    offset: i32,
+}
+
+impl CompiledShape {
+    /// Compile `shape` for the given date. Returns None when it cannot be rendered, e.g.
+    /// `{m}` for a two-digit month or an era token without an era.
+    fn new(
+        shape: &'static str,
+        yyyy: i32,
+        mm: u32,
+        dd: Option<u32>,
+        era: Option<(&Era, i32)>,
+        offset: i32,
+    ) -> Option<Self> {
+        let tokens = shape_tokens(shape);
+        let mut pattern = String::new();
+        for token in &tokens {
+            let part = match token {
+                ShapeToken::Placeholder(p) => match *p {
+                    "yyyy" => yyyy.to_string(),
+                    "yy" => format!("{:02}", yyyy.rem_euclid(100)),
+                    "mm" => format!("{:02}", mm),
+                    "m" if mm < 10 => mm.to_string(),
+                    "dd" => match dd {
+                        Some(dd) => format!("{:02}", dd),
+                        None => r"(?:0[1-9]|[12]\d|3[01])".to_string(),
+                    },
+                    "mmm" => format!("(?i:{})", MONTH_ABBR_EN.get(mm as usize - 1)?),
+                    "mon_ja" => MONTH_NAMES_JA.get(mm as usize - 1)?.to_string(),
+                    "gengo" => era?.0.name.to_string(),
+                    "g" => era?.0.initial.to_string(),
+                    "eyy" => format!("{:02}", era?.1),
+                    "ey" if era?.1 == 1 => "(?:元|0?1)".to_string(),
+                    "ey" => format!("0?{}", era?.1),
+                    _ => return None,
+                },
+                ShapeToken::Separator => "[-_. ]".to_string(),
+                ShapeToken::Literal(c) => regex::escape(&c.to_string()),
+            };
+            pattern.push_str(&format!("({})", part));
+        }
+        Some(CompiledShape {
+            shape,
+            re: Regex::new(&pattern).unwrap(),
+            before: tokens.first().and_then(token_boundary),
+            after: tokens.last().and_then(token_boundary),
+            tokens,
+            keep_day: dd.is_none(),
+            offset,
+        })
+    }
+
+    fn has_month(&self) -> bool {
+        self.shape.contains("{m")
+    }
+
+    /// Replace every occurrence of the shape. Returns None when it does not occur.
+    fn apply(&self, name: &str) -> Option<String> {
+        let mut out = String::new();
+        let (mut last, mut pos) = (0, 0);
+        while let Some(caps) = self.re.captures_at(name, pos) {
+            let m = caps.get(0).unwrap();
+            let bounded = self.before.is_none_or(|touches| !name[..m.start()].chars().next_back().is_some_and(touches))
+                && self.after.is_none_or(|touches| !name[m.end()..].chars().next().is_some_and(touches));
+            if !bounded {
+                pos = m.start() + name[m.start()..].chars().next().map_or(1, char::len_utf8);
+                continue;
+            }
+            out.push_str(&name[last..m.start()]);
+            for (i, token) in self.tokens.iter().enumerate() {
+                match token {
+                    ShapeToken::Placeholder("dd") if self.keep_day => out.push_str(&caps[i + 1]),
+                    ShapeToken::Placeholder(p) => out.push_str(&offset_placeholder(p, self.offset)),
+                    _ => out.push_str(&caps[i + 1]),
+                }
+            }
+            last = m.end();
+            pos = m.end();
+        }
+        if last == 0 {
+            return None;
+        }
+        out.push_str(&name[last..]);
+        Some(out)
+    }
+}
+
+/// Compile `shapes` for one date; shapes with era tokens once per era covering the year.
+/// Around an era change both eras are tried ("平成31年5月" as well as "令和元年5月").
+fn compile_shapes<'a>(
+    shapes: impl Iterator<Item = &'a &'static str>,
+    yyyy: i32,
+    mm: u32,
+    dd: Option<u32>,
+    offset: i32,
+) -> Vec<CompiledShape> {
+    let eras: Vec<(&Era, i32)> = JAPANESE_ERAS
+        .iter()
+        .enumerate()
+        .filter(|(i, era)| {
+            let ends = if *i == 0 { i32::MAX } else { JAPANESE_ERAS[i - 1].start.0 };
+            yyyy >= era.start.0 && yyyy <= ends
+        })
+        .map(|(_, era)| (era, yyyy - era.start.0 + 1))
+        .collect();
+
+    shapes
+        .flat_map(|shape| {
+            let era_choices: Vec<Option<(&Era, i32)>> = if shape.contains("{g") {
+                eras.iter().copied().map(Some).collect()
+            } else {
+                vec![None]
+            };
+            era_choices
+                .into_iter()
+                .filter_map(|era| CompiledShape::new(shape, yyyy, mm, dd, era, offset))
+                .collect::<Vec<_>>()
+        })
+        .collect()
+}
+
+/// Year and month `offset` months away.
+fn shift_month(yyyy: i32, mm: u32, offset: i32) -> (i32, u32) {
+    let index = yyyy * 12 + mm as i32 - 1 + offset;
+    (index.div_euclid(12), index.rem_euclid(12) as u32 + 1)
+}
+
+/// Date parts found in one file name.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NameDate {
+    pub normalized: String,
+    /// Entry of `DATE_SHAPES` that matched first
+    pub shape: Option<&'static str>,
+    /// Months between the date in the name and the folder date (0 = the folder's own month);
+    /// None when no month was recognised, e.g. only a year
+    pub offset: Option<i32>,
+}
+
+/// File name date patterns of one snapshot date, compiled once and reused for every file
+/// of the snapshot (see `DATE_SHAPES`).
+#[derive(Debug, Clone)]
+pub struct DateNormalizer {
+    /// Shapes of the folder's own date, in `DATE_SHAPES` order
+    own: Vec<CompiledShape>,
+    /// Lone month (and day) fallbacks
+    lone: Vec<CompiledShape>,
+    /// Month-bearing shapes of the nearby months, nearest first
+    nearby: Vec<Vec<CompiledShape>>,
+}
+
+impl DateNormalizer {
+    /// Patterns for `date`; `{dd}` is only normalized for daily and weekly snapshots, and
+    /// dates up to `window` months away are recognised as well.
+    pub fn new(date: NaiveDate, granularity: Granularity, window: u32) -> Self {
+        let day = match granularity {
+            Granularity::Monthly => None,
+            Granularity::Daily | Granularity::Weekly => Some(date.day()),
+        };
+        Self::for_month(date.year(), date.month(), day, window)
+    }
+
+    fn for_month(yyyy: i32, mm: u32, dd: Option<u32>, window: u32) -> Self {
+        let lone = LONE_SHAPES.iter().filter(|sh| dd.is_some() || **sh != "{dd}");
+        let nearby = (1..=window as i32)
+            .flat_map(|k| [-k, k])
+            .map(|offset| {
+                let (y, m) = shift_month(yyyy, mm, offset);
+                // The day of another month's date is never this folder's day
+                compile_shapes(DATE_SHAPES.iter().filter(|sh| sh.contains("{m")), y, m, None, offset)
+            })
+            .collect();
+        DateNormalizer {
+            own: compile_shapes(DATE_SHAPES.iter(), yyyy, mm, dd, 0),
+            lone: compile_shapes(lone, yyyy, mm, dd, 0),
+            nearby,
+        }
+    }
+
+    /// Normalize the date parts of a file name, returning the most specific shape matched.
+    /// The lone month (and day) is only replaced when nothing else carried the month, so
+    /// unrelated numbers such as "Report_12_items_12-2024" or "File101_01-2025" are kept.
+    /// Dates of the nearby months are recognised in what the folder's own month left, nearest
+    /// first and earlier months before later ones, e.g.
+    /// "Closing_02-2025.xlsx" in the March folder becomes "Closing_{mm-1}-{yyyy-1m}.xlsx"
+    /// with offset -1; the offset is that of the first month found.
+    pub fn normalize(&self, name: &str) -> NameDate {
+        let own_month = self.own.iter().filter(|sh| sh.has_month());
+        let year_only = self.own.iter().filter(|sh| !sh.has_month());
+        let mut s = name.to_string();
+        let mut matched: Option<&CompiledShape> = None;
+        let mut month: Option<&CompiledShape> = None;
+        for shape in own_month.chain(self.nearby.iter().flatten()).chain(year_only) {
+            if let Some(out) = shape.apply(&s) {
+                s = out;
+                matched.get_or_insert(shape);
+                if shape.has_month() {
+                    month.get_or_insert(shape);
+                }
+            }
+        }
+        if month.is_none() {
+            for shape in &self.lone {
+                if let Some(out) = shape.apply(&s) {
+                    s = out;
+                    matched.get_or_insert(shape);
+                    if shape.has_month() {
+                        month.get_or_insert(shape);
+                    }
+                }
+            }
+        }
+        NameDate {
+            normalized: s,
+            shape: matched.map(|sh| sh.shape),
+            // A lone year or day does not tell which month the name refers to
+            offset: month.map(|sh| sh.offset),
+        }
+    }
+
+    /// Normalize the file name of a relative path and the directory segments at `dir_depths`
+    /// (1 = directly below the snapshot root), e.g. "Main/2024-12/Detail_12.csv" with depth 2
+    /// becomes "Main/{yyyy}-{mm}/Detail_{mm}.csv". Returns the date shape and offset of the
+    /// file name, or else of the first normalized directory.
+    pub fn normalize_rel_path(&self, rel_path: &str, dir_depths: &[usize]) -> NameDate {
+        let rel_path = rel_path.replace('\\', "/");
+        let segments: Vec<&str> = rel_path.split('/').collect();
+        let last = segments.len() - 1;
+        let mut file_date = None;
+        let mut dir_date = None;
+        let normalized: Vec<String> = segments
+            .iter()
+            .enumerate()
+            .map(|(i, segment)| {
+                if i == last || dir_depths.contains(&(i + 1)) {
+                    let found = self.normalize(segment);
+                    let normalized = found.normalized.clone();
+                    if i == last {
+                        file_date = Some(found);
+                    } else if dir_date.is_none() && found.shape.is_some() {
+                        dir_date = Some(found);
+                    }
+                    normalized
+                } else {
+                    segment.to_string()
+                }
+            })
+            .collect();
+        let found = file_date.filter(|f| f.shape.is_some()).or(dir_date);
+        NameDate {
+            normalized: normalized.join("/"),
+            shape: found.as_ref().and_then(|f| f.shape),
+            offset: found.and_then(|f| f.offset),
+        }
+    }
+}
+
+pub fn normalize_rel_path(rel_path: &str, yyyy: i32, mm: u32) -> String {
+    map_file_name(rel_path, |file| normalize_filename(file, yyyy, mm))
+}
+
+fn map_file_name(rel_path: &str, normalize: impl FnOnce(&str) -> String) -> String {
+    // Only normalize the file name part, keep directories as they are.
+    // Unify separators first so Windows-style paths split on every platform.
+    let rel_path = rel_path.replace('\\', "/");
+    let p = Path::new(&rel_path);
+    let file = p.file_name().map(|s| s.to_string_lossy().to_string()).unwrap_or_default();
+    let normalized_file = normalize(&file);
+    if let Some(parent) = p.parent() {
+        if parent.as_os_str().is_empty() {
+            normalized_file
+        } else {
+            format!("{}/{}", parent.to_string_lossy().replace('\\', "/"), normalized_file)
+        }
+    } else {
+        normalized_file
+    }
+}
+
+/// User-defined rewrite of normalized relative paths (run numbers, version suffixes,
+/// export timestamps), applied after the built-in date normalization.
+#[derive(Debug, Clone)]
+pub struct NormalizationRule {
+    pub pattern: Regex,
+    /// Replacement text; `$1`/`${name}` refer to capture groups
+    pub replacement: String,
+}
+
+impl std::str::FromStr for NormalizationRule {
+    type Err = String;
+
+    /// Parse "REGEX => REPLACEMENT", e.g. `_v\d+ =>` to drop version suffixes.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (pattern, replacement) = s
+            .split_once("=>")
+            .ok_or_else(|| format!("invalid rule '{}' (expected REGEX => REPLACEMENT)", s))?;
+        let pattern = Regex::new(pattern.trim()).map_err(|e| format!("invalid rule '{}': {}", s, e))?;
+        Ok(NormalizationRule {
+            pattern,
+            replacement: replacement.trim().to_string(),
+        })
+    }
+}
+
+/// Parse a rules file: one "REGEX => REPLACEMENT" per line; blank lines and `#` comments
+/// are ignored. Errors name the offending line.
+pub fn parse_normalization_rules(text: &str) -> Result<Vec<NormalizationRule>, String> {
+    text.lines()
+        .enumerate()
+        .map(|(i, l)| (i + 1, l.trim()))
+        .filter(|(_, l)| !l.is_empty() && !l.starts_with('#'))
+        .map(|(n, l)| l.parse().map_err(|e| format!("line {}: {}", n, e)))
+        .collect()
+}
+
+/// One rule that changed a path.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NormalizationStep {
+    /// Index of the rule in `CollectOptions::rules`
+    pub rule: usize,
+    pub result: String,
+}
+
+/// How a relative path was normalized, as shown by `--explain-normalization`.
+#[derive(Debug, Clone)]
+pub struct NormalizationTrace {
+    pub rel_path: String,
+    pub date_shape: Option<&'static str>,
+    /// See `FileInfo::date_offset`
+    pub date_offset: Option<i32>,
+    /// Path after the built-in date normalization (unchanged when it is disabled)
+    pub date_normalized: String,
+    /// Rules that changed the path, in order
+    pub steps: Vec<NormalizationStep>,
+}
+
+impl NormalizationTrace {
+    pub fn normalized(&self) -> &str {
+        self.steps.last().map_or(&self.date_normalized, |s| &s.result)
+    }
+}
+
+/// Apply the rules in order to the whole relative path.
+pub fn apply_normalization_rules(path: &str, rules: &[NormalizationRule]) -> (String, Vec<NormalizationStep>) {
+    let mut current = path.to_string();
+    let mut steps = Vec::new();
+    for (i, rule) in rules.iter().enumerate() {
+        let next = rule.pattern.replace_all(&current, rule.replacement.as_str()).into_owned();
+        if next != current {
+            steps.push(NormalizationStep { rule: i, result: next.clone() });
+            current = next;
+        }
+    }
+    (current, steps)
 }
 
-pub fn normalize_filename(name: &str, yyyy: i32, mm: u32) -> String {
-    // Replace the four-digit year first
-    let with_year = name.replace(&yyyy.to_string(), "{yyyy}");
-    // Then replace the zero-padded month
-    let month_str = format!("{:02}", mm);
-    with_year.replace(&month_str, "{mm}")
+/// Normalize a relative path as `collect_files_with` does, keeping every step.
+pub fn trace_normalization(rel_path: &str, date: NaiveDate, opts: &CollectOptions) -> NormalizationTrace {
+    let normalizer = DateNormalizer::new(date, opts.granularity, opts.month_window);
+    trace_normalization_with(rel_path, &normalizer, opts)
 }
 
-pub fn normalize_rel_path(rel_path: &str, yyyy: i32, mm: u32) -> String {
-    // Only normalize the file name part, keep directories as they are
-    let p = Path::new(rel_path);
-    let file = p.file_name().map(|s| s.to_string_lossy().to_string()).unwrap_or_default();
-    let normalized_file = normalize_filename(&file, yyyy, mm);
-    if let Some(parent) = p.parent() {
-        if parent.as_os_str().is_empty() {
-            normalized_file
-        } else {
-            format!("{}/{}", parent.to_string_lossy().replace('\\', "/"), normalized_file)
-        }
+/// `trace_normalization` with the patterns of the snapshot date already compiled, for
+/// tracing every file of a snapshot.
+pub fn trace_normalization_with(
+    rel_path: &str,
+    normalizer: &DateNormalizer,
+    opts: &CollectOptions,
+) -> NormalizationTrace {
+    let found = if opts.detect_filename_dates {
+        normalizer.normalize_rel_path(rel_path, &opts.dir_depths)
     } else {
-        normalized_file
+        NameDate {
+            normalized: rel_path.to_string(),
+            shape: None,
+            offset: None,
+        }
+    };
+    let date_normalized = found.normalized;
+    let (_, steps) = apply_normalization_rules(&date_normalized, &opts.rules);
+    NormalizationTrace {
+        rel_path: rel_path.to_string(),
+        date_shape: found.shape,
+        date_offset: found.offset,
+        date_normalized,
+        steps,
+    }
+}
+
+/// Hex digest of a file's content.
+pub fn hash_file(path: &Path, algorithm: HashAlgorithm) -> io::Result<String> {
+    use std::io::Read;
+
+    let mut file = fs::File::open(path)?;
+    let mut buf = [0u8; 64 * 1024];
+    match algorithm {
+        HashAlgorithm::Sha256 => {
+            let mut hasher = Sha256::new();
+            loop {
+                let n = file.read(&mut buf)?;
+                if n == 0 {
+                    break;
+                }
+                hasher.update(&buf[..n]);
+            }
+            Ok(hasher
+                .finalize()
+                .iter()
+                .map(|b| format!("{:02x}", b))
+                .collect())
+        }
+        HashAlgorithm::Xxh3 => {
+            let mut hasher = xxhash_rust::xxh3::Xxh3::new();
+            loop {
+                let n = file.read(&mut buf)?;
+                if n == 0 {
+                    break;
+                }
+                hasher.update(&buf[..n]);
+            }
+            Ok(format!("{:016x}", hasher.digest()))
+        }
     }
 }
 
@@ -60,11 +1228,21 @@ pub fn collect_files(
     max_depth: usize,
     detect_filename_dates: bool,
 ) -> Vec<FileInfo> {
+    let opts = CollectOptions {
+        max_depth,
+        detect_filename_dates,
+        ..CollectOptions::default()
+    };
+    collect_files_with(root, date, &opts)
+}
+
+pub fn collect_files_with(root: &Path, date: NaiveDate, opts: &CollectOptions) -> Vec<FileInfo> {
     let mut out = Vec::new();
+    let normalizer = DateNormalizer::new(date, opts.granularity, opts.month_window);
 
     for entry in WalkDir::new(root)
         .min_depth(1)
-        .max_depth(max_depth)
+        .max_depth(opts.max_depth)
         .into_iter()
         .filter_map(Result::ok)
         .filter(|e| e.file_type().is_file())
@@ -88,7 +1266,7 @@ pub fn collect_files(
             .map(|t| {
                 let mut dt: DateTime<Local> = DateTime::from(t);
                 if dt.second() >= 30 {
-                    dt = dt + Duration::minutes(1);
+                    dt += Duration::minutes(1);
                 }
                 dt.format("%Y/%m/%d %H:%M").to_string()
             })
@@ -98,115 +1276,431 @@ pub fn collect_files(
             .map(|t| {
                 let mut dt: DateTime<Local> = DateTime::from(t);
                 if dt.second() >= 30 {
-                    dt = dt + Duration::minutes(1);
+                    dt += Duration::minutes(1);
                 }
                 dt.format("%Y/%m/%d %H:%M").to_string()
             })
             .unwrap_or_else(|_| "N/A".into());
 
         let file_name = entry.file_name().to_string_lossy().to_string();
-        let normalized_rel_path = if detect_filename_dates {
-            normalize_rel_path(&rel_path, date.year(), date.month())
-        } else {
-            rel_path.clone()
-        };
+        let content_hash = opts.hash.and_then(|algo| hash_file(entry.path(), algo).ok());
+
+        let trace = trace_normalization_with(&rel_path, &normalizer, opts);
+        let normalized_rel_path = trace.normalized().to_string();
+        let date_shape = trace.date_shape.map(str::to_string);
+        let date_offset = trace.date_offset;
 
         out.push(FileInfo {
             actual_name: file_name,
             size,
             created,
             modified,
-            date_str: date.format("%Y-%m").to_string(),
+            date_str: opts.period_label(date),
             rel_path,
             normalized_rel_path,
+            content_hash,
+            date_shape,
+            date_offset,
         });
     }
 
     out
 }
 
+/// Files of one snapshot that normalized to the same `normalized_rel_path`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NormalizationCollision {
+    pub date_str: String,
+    pub normalized_rel_path: String,
+    /// Actual relative paths, sorted
+    pub rel_paths: Vec<String>,
+}
+
+/// Detect files of one snapshot sharing a normalized path and keep them apart in every
+/// snapshot. Each file of a colliding path gets a "#" suffix taken from its date shape
+/// (e.g. "#{mm}-{yyyy}"), so it lands in the same series in months where the other file
+/// is missing. When the shapes do not tell the files apart, the actual path is used.
+pub fn disambiguate_collisions<'a>(
+    snapshots: impl IntoIterator<Item = &'a mut Snapshot>,
+) -> Vec<NormalizationCollision> {
+    let mut snapshots: Vec<&mut Snapshot> = snapshots.into_iter().collect();
+
+    // colliding normalized path -> whether date shapes tell its files apart
+    let mut by_shape: BTreeMap<String, bool> = BTreeMap::new();
+    let mut collisions = Vec::new();
+    for snap in &snapshots {
+        let mut by_norm: BTreeMap<&str, Vec<&FileInfo>> = BTreeMap::new();
+        for f in &snap.files {
+            by_norm.entry(&f.normalized_rel_path).or_default().push(f);
+        }
+        for (norm, files) in by_norm.into_iter().filter(|(_, v)| v.len() > 1) {
+            let shapes: BTreeSet<&str> = files.iter().filter_map(|f| f.date_shape.as_deref()).collect();
+            *by_shape.entry(norm.to_string()).or_insert(true) &= shapes.len() == files.len();
+            let mut rel_paths: Vec<String> = files.iter().map(|f| f.rel_path.clone()).collect();
+            rel_paths.sort();
+            collisions.push(NormalizationCollision {
+                date_str: snap.date_str.clone(),
+                normalized_rel_path: norm.to_string(),
+                rel_paths,
+            });
+        }
+    }
+
+    for snap in snapshots.iter_mut() {
+        for f in snap.files.iter_mut() {
+            let distinct_shapes = match by_shape.get(&f.normalized_rel_path) {
+                Some(&distinct) => distinct,
+                None => continue,
+            };
+            let key = match &f.date_shape {
+                Some(shape) if distinct_shapes => shape.clone(),
+                _ => f.rel_path.clone(),
+            };
+            f.normalized_rel_path = format!("{}#{}", f.normalized_rel_path, key);
+        }
+    }
+    collisions
+}
+
 pub fn extract_dates_from_template(template: &str) -> Vec<NaiveDate> {
-    use std::path::Component;
+    extract_dates_from_template_with(template, &DiscoverOptions::default())
+}
 
-    let tpl = PathBuf::from(template);
+/// Placeholders understood in folder templates, with the pattern each one captures.
+const DATE_PLACEHOLDERS: &[(&str, &str)] = &[
+    ("yyyy", r"\d{4}"),
+    ("yy", r"\d{2}"),
+    ("mm", r"\d{1,2}"), // allow 1 or 2 digits
+    ("m", r"\d{1,2}"),
+    ("mmm", r"(?i:Jan|Feb|Mar|Apr|May|Jun|Jul|Aug|Sep|Oct|Nov|Dec)"),
+    ("mon_ja", "睦月|如月|弥生|卯月|皐月|水無月|文月|葉月|長月|神無月|霜月|師走"),
+    ("dd", r"\d{1,2}"),
+    ("gengo", "令和|平成|昭和"),
+    ("g", "[RHS]"),
+    ("ey", r"\d{1,2}|元"),
+    ("eyy", r"\d{2}"),
+    ("fy", r"\d{4}"),
+    ("q", "[1-4]"),
+];
 
-    // Helper: detect placeholders
-    let has_ph = |s: &str| s.contains("{yyyy}") || s.contains("{mm}") || s.contains("{dd}");
+fn has_placeholder(segment: &str) -> bool {
+    DATE_PLACEHOLDERS
+        .iter()
+        .any(|(name, _)| segment.contains(&format!("{{{}}}", name)))
+}
 
-    // Find the deepest (right-most) path segment that contains placeholders.
-    // If none, we fallback to the parent directory name (current behavior).
-    let mut seg_with_ph: Option<(PathBuf, String)> = None;
-    let mut current = PathBuf::new();
-    for comp in tpl.components() {
-        match comp {
-            Component::Normal(os) => {
-                current.push(os);
+/// Regex for one template segment, plus the placeholder behind each capture group in order.
+/// Groups are positional so a placeholder may appear more than once in a segment;
+/// `*` and `?` in the literal text are wildcards.
+/// Alternations are grouped, so anchoring applies to the whole pattern.
+fn segment_regex(segment: &str, anchored: bool) -> (Regex, Vec<&'static str>) {
+    let mut re_str = String::from(if anchored { "^" } else { "" });
+    let mut names = Vec::new();
+    let mut rest = segment;
+    loop {
+        let next = DATE_PLACEHOLDERS
+            .iter()
+            .filter_map(|(name, pat)| rest.find(&format!("{{{}}}", name)).map(|pos| (pos, *name, *pat)))
+            .min_by_key(|(pos, _, _)| *pos);
+        match next {
+            Some((pos, name, pat)) => {
+                re_str.push_str(&glob_to_regex(&rest[..pos]));
+                re_str.push_str(&format!("({})", pat));
+                names.push(name);
+                rest = &rest[pos + name.len() + 2..];
             }
-            other => {
-                current.push(other.as_os_str());
+            None => {
+                re_str.push_str(&glob_to_regex(rest));
+                break;
             }
         }
     }
-    // Now `current` == `tpl`. Walk upwards to find a segment with placeholders.
-    let mut cursor = tpl.clone();
-    while let Some(name) = cursor.file_name().map(|s| s.to_string_lossy().to_string()) {
-        if has_ph(&name) {
-            seg_with_ph = Some((cursor.clone(), name));
-            break;
-        }
-        if !cursor.pop() {
-            break;
+    if anchored {
+        re_str.push('$');
+    }
+    (Regex::new(&re_str).expect("Invalid regex from template"), names)
+}
+
+/// Date field ("year", "month" or "day") and value captured by a placeholder.
+fn placeholder_value(name: &str, text: &str) -> Option<(&'static str, i32)> {
+    let month_of = |names: &[&str; 12]| {
+        names
+            .iter()
+            .position(|n| n.eq_ignore_ascii_case(text))
+            .map(|i| i as i32 + 1)
+    };
+    match name {
+        "yyyy" => Some(("year", text.parse().ok()?)),
+        "yy" => Some(("year", 2000 + text.parse::<i32>().ok()?)),
+        "mm" | "m" => Some(("month", text.parse().ok()?)),
+        "mmm" => Some(("month", month_of(&MONTH_ABBR_EN)?)),
+        "mon_ja" => Some(("month", month_of(&MONTH_NAMES_JA)?)),
+        "dd" => Some(("day", text.parse().ok()?)),
+        "gengo" => Some(("era", JAPANESE_ERAS.iter().position(|e| e.name == text)? as i32)),
+        "g" => Some(("era", JAPANESE_ERAS.iter().position(|e| e.initial == text)? as i32)),
+        "ey" | "eyy" if text == "元" => Some(("era_year", 1)),
+        "ey" | "eyy" => Some(("era_year", text.parse().ok()?)),
+        "fy" => Some(("fiscal_year", text.parse().ok()?)),
+        "q" => Some(("quarter", text.parse().ok()?)),
+        _ => None,
+    }
+}
+
+/// Why a folder next to the template's date folders was not used as-is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FolderIssueKind {
+    /// Does not match the template segment
+    NoMatch,
+    /// Matches, but the captures are not a valid date (e.g. month 13)
+    InvalidDate,
+    /// Matches, but repeated placeholders disagree (e.g. `{yyyy}` and `{yy}`)
+    Inconsistent,
+    /// One of several folders resolving to the same date
+    Ambiguous,
+}
+
+impl FolderIssueKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            FolderIssueKind::NoMatch => "no_match",
+            FolderIssueKind::InvalidDate => "invalid_date",
+            FolderIssueKind::Inconsistent => "inconsistent",
+            FolderIssueKind::Ambiguous => "ambiguous",
         }
     }
+}
 
-    // Decide base_dir (to scan) and folder_tpl (to turn into regex pattern).
-    let (base_dir, folder_tpl) = if let Some((seg_path, name)) = seg_with_ph {
-        // Scan the parent directory of the placeholder segment.
-        let bd = seg_path.parent().unwrap_or_else(|| Path::new(".")).to_path_buf();
-        (bd, name)
-    } else {
-        // Fallback: old behavior (parent of parent), but this likely yields 0 matches
-        let main_dir = tpl.parent().unwrap_or_else(|| Path::new(".")).to_path_buf();
-        let base_dir = main_dir.parent().unwrap_or_else(|| Path::new(".")).to_path_buf();
-        let folder_tpl = main_dir
-            .file_name()
-            .map(|s| s.to_string_lossy().to_string())
-            .unwrap_or_default();
-        (base_dir, folder_tpl)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FolderIssue {
+    pub kind: FolderIssueKind,
+    pub path: PathBuf,
+    /// Date the folder resolves to, for ambiguous folders
+    pub date: Option<NaiveDate>,
+}
+
+/// Dates discovered from a template, plus the sibling folders that were skipped or
+/// matched ambiguously.
+#[derive(Debug, Clone, Default)]
+pub struct Discovery {
+    pub dates: Vec<NaiveDate>,
+    pub issues: Vec<FolderIssue>,
+}
+
+/// Discover snapshot dates from the folders matching the template.
+/// See `discover_dates` for the matching rules and a report of skipped folders.
+pub fn extract_dates_from_template_with(template: &str, opts: &DiscoverOptions) -> Vec<NaiveDate> {
+    discover_dates(template, opts).dates
+}
+
+/// Discover snapshot dates from the folders matching the template.
+/// Every path segment with placeholders is matched level by level (e.g. `/data/{yyyy}/{mm}/Main`);
+/// placeholders for the same field (e.g. `{yyyy}` and `{yy}`) must agree everywhere, and an
+/// era year (`{gengo}`/`{g}` with `{ey}`/`{eyy}`) must agree with a Gregorian year if both appear.
+/// Fiscal folders (`FY{fy}/Q{q}`) are dated the first month of the quarter (or fiscal year).
+/// Monthly snapshots are dated the 1st; daily and weekly snapshots keep the `{dd}` day
+/// (the 1st when the template has none), and weekly keeps the latest folder of each week.
+///
+/// Segments may contain `*`/`?` wildcards (e.g. `参照{yyyy}_{mm}月データ*`); see `resolve_path`
+/// for choosing among the matching folders. Segments match anywhere in a folder name unless
+/// `opts.strict` anchors them to the whole name. Sibling folders that do not match, and folders sharing a date, are reported in
+/// `Discovery::issues`, except wildcard matches that `opts.match_policy` chooses between;
+/// each date is returned once.
+pub fn discover_dates(template: &str, opts: &DiscoverOptions) -> Discovery {
+    let granularity = opts.granularity;
+    let template = normalize_separators(template);
+    let segments: Vec<String> = Path::new(&template)
+        .components()
+        .map(|c| c.as_os_str().to_string_lossy().to_string())
+        .collect();
+    // Wildcard segments before the last placeholder are scanned as well
+    let (first, last) = match (
+        segments.iter().position(|s| has_placeholder(s) || has_glob(s)),
+        segments.iter().rposition(|s| has_placeholder(s)),
+    ) {
+        (Some(first), Some(last)) => (first, last),
+        // Nothing to discover without placeholders
+        _ => return Discovery::default(),
     };
 
-    // Build regex: escape everything then re-insert capture groups.
-    let mut re_str = regex::escape(&folder_tpl);
-    re_str = re_str.replace(r"\{yyyy\}", r"(?P<yyyy>\d{4})");
-    re_str = re_str.replace(r"\{mm\}",   r"(?P<mm>\d{1,2})");  // allow 1 or 2 digits
-    re_str = re_str.replace(r"\{dd\}",   r"(?P<dd>\d{1,2})");
+    let mut base: PathBuf = segments[..first].iter().collect();
+    if base.as_os_str().is_empty() {
+        base = PathBuf::from(".");
+    }
 
-    let re = Regex::new(&re_str).expect("Invalid regex from template");
+    let mut issues = Vec::new();
+    let issue = |kind, path: PathBuf| FolderIssue { kind, path, date: None };
 
-    // Debug (optional):
-    // eprintln!("[debug] base_dir={}", base_dir.display());
-    // eprintln!("[debug] folder_tpl='{}' -> regex='{}'", folder_tpl, re_str);
+    // Directories matched so far, with the placeholder values captured on the way
+    let mut frontier: Vec<(PathBuf, HashMap<&'static str, i32>)> = vec![(base, HashMap::new())];
+    for (i, segment) in segments.iter().enumerate().take(last + 1).skip(first) {
+        if !has_placeholder(segment) && !has_glob(segment) {
+            for (dir, _) in frontier.iter_mut() {
+                dir.push(segment);
+            }
+            continue;
+        }
 
-    let mut dates = Vec::new();
-    if let Ok(entries) = fs::read_dir(&base_dir) {
-        for entry in entries.flatten() {
-            if let Some(name) = entry.file_name().to_str() {
-                if let Some(caps) = re.captures(name) {
-                    if let (Some(y), Some(m)) = (
-                        caps.name("yyyy").and_then(|m| m.as_str().parse::<i32>().ok()),
-                        caps.name("mm").and_then(|m| m.as_str().parse::<u32>().ok()),
-                    ) {
-                        if let Some(d) = NaiveDate::from_ymd_opt(y, m, 1) {
-                            dates.push(d);
+        let (re, names) = segment_regex(segment, opts.strict);
+        let mut next = Vec::new();
+        for (dir, captured) in &frontier {
+            let entries = match fs::read_dir(dir) {
+                Ok(entries) => entries,
+                Err(_) => continue,
+            };
+            for entry in entries.flatten() {
+                let is_dir = entry.file_type().map(|t| t.is_dir()).unwrap_or(false);
+                // Anything but the final path segment must be a directory
+                if i + 1 < segments.len() && !is_dir {
+                    continue;
+                }
+                let name = entry.file_name().to_string_lossy().to_string();
+                let caps = match re.captures(&name) {
+                    Some(caps) => caps,
+                    None => {
+                        if is_dir {
+                            issues.push(issue(FolderIssueKind::NoMatch, dir.join(&name)));
                         }
+                        continue;
                     }
+                };
+                let mut merged = captured.clone();
+                let mut valid = true;
+                let mut consistent = true;
+                for (g, ph) in names.iter().enumerate() {
+                    match caps.get(g + 1).and_then(|m| placeholder_value(ph, m.as_str())) {
+                        Some((field, v)) => consistent &= *merged.entry(field).or_insert(v) == v,
+                        None => valid = false,
+                    }
+                }
+                match (valid, consistent) {
+                    (true, true) => next.push((dir.join(&name), merged)),
+                    (false, _) => issues.push(issue(FolderIssueKind::InvalidDate, dir.join(&name))),
+                    (true, false) => issues.push(issue(FolderIssueKind::Inconsistent, dir.join(&name))),
                 }
             }
         }
+        frontier = next;
     }
 
-    dates.sort_unstable();
-    dates
+    let mut by_date: BTreeMap<NaiveDate, Vec<PathBuf>> = BTreeMap::new();
+    for (path, caps) in frontier {
+        match capture_date(&caps, opts) {
+            Ok(date) => by_date.entry(date).or_default().push(path),
+            Err(kind) => issues.push(issue(kind, path)),
+        }
+    }
+    for (date, paths) in by_date.iter().filter(|(_, paths)| paths.len() > 1) {
+        if opts.match_policy != MatchPolicy::Error
+            && paths.iter().all(|p| matches_resolved_glob(p, &template, *date, first, last, opts))
+        {
+            // Only the wildcards differ: resolve_path picks one of them by policy
+            continue;
+        }
+        let mut paths = paths.clone();
+        paths.sort();
+        for path in paths {
+            issues.push(FolderIssue {
+                kind: FolderIssueKind::Ambiguous,
+                path,
+                date: Some(*date),
+            });
+        }
+    }
+
+    // BTreeMap keys are sorted and unique
+    let mut dates: Vec<NaiveDate> = by_date.into_keys().collect();
+    if granularity == Granularity::Weekly {
+        // sorted ascending, so the last date of each week wins
+        let mut latest: Vec<NaiveDate> = Vec::new();
+        for d in dates {
+            match latest.last_mut() {
+                Some(prev) if prev.iso_week() == d.iso_week() => *prev = d,
+                _ => latest.push(d),
+            }
+        }
+        dates = latest;
+    }
+    issues.sort_by(|a, b| a.path.cmp(&b.path));
+    Discovery { dates, issues }
+}
+
+/// Whether the trailing segments of `path` match the template resolved for `date`
+/// (segments `first..=last`), i.e. the folder is one of the `*`/`?` matches for that date.
+fn matches_resolved_glob(
+    path: &Path,
+    template: &str,
+    date: NaiveDate,
+    first: usize,
+    last: usize,
+    opts: &DiscoverOptions,
+) -> bool {
+    let resolved = resolve_template_with(template, date, opts.fiscal_start_month);
+    let segments: Vec<String> = resolved
+        .components()
+        .take(last + 1)
+        .skip(first)
+        .map(|c| c.as_os_str().to_string_lossy().to_string())
+        .collect();
+    let names: Vec<String> = path
+        .components()
+        .rev()
+        .take(segments.len())
+        .map(|c| c.as_os_str().to_string_lossy().to_string())
+        .collect();
+    names.len() == segments.len()
+        && segments.iter().rev().zip(&names).all(|(segment, name)| {
+            Regex::new(&format!("^{}$", glob_to_regex(segment)))
+                .expect("Invalid regex from template")
+                .is_match(name)
+        })
+}
+
+/// Snapshot date from the captured date fields. Fails with `Inconsistent` when a Gregorian
+/// year disagrees with the era or fiscal period, and `InvalidDate` otherwise.
+fn capture_date(caps: &HashMap<&'static str, i32>, opts: &DiscoverOptions) -> Result<NaiveDate, FolderIssueKind> {
+    use FolderIssueKind::{Inconsistent, InvalidDate};
+
+    let era_year = match (caps.get("era"), caps.get("era_year")) {
+        (Some(era), Some(ey)) => Some(from_wareki(&JAPANESE_ERAS[*era as usize], *ey)),
+        _ => None,
+    };
+    let fiscal = match caps.get("fiscal_year") {
+        Some(fy) => {
+            let q = caps.get("quarter").map(|q| *q as u32).unwrap_or(1);
+            Some((*fy, fiscal_quarter_start(*fy, q, opts.fiscal_start_month).ok_or(InvalidDate)?))
+        }
+        None => None,
+    };
+    let y = match (caps.get("year"), era_year) {
+        (Some(y), Some(from_era)) if *y != from_era => return Err(Inconsistent),
+        (Some(y), _) => Some(*y),
+        (None, from_era) => from_era,
+    };
+    let (y, m) = match (y, caps.get("month").map(|m| *m as u32), fiscal) {
+        // A fiscal period given next to the month must contain it
+        (y, Some(m), Some((fy, start))) => {
+            let y = y.unwrap_or(if m >= opts.fiscal_start_month {
+                start.year()
+            } else {
+                start.year() + 1
+            });
+            let date = NaiveDate::from_ymd_opt(y, m, 1).ok_or(InvalidDate)?;
+            let (date_fy, date_q) = fiscal_year_quarter(date, opts.fiscal_start_month);
+            if date_fy != fy || caps.get("quarter").is_some_and(|q| *q as u32 != date_q) {
+                return Err(Inconsistent);
+            }
+            (y, m)
+        }
+        (Some(y), Some(m), None) => (y, m),
+        (None, None, Some((_, start))) => (start.year(), start.month()),
+        _ => return Err(InvalidDate),
+    };
+    let day = match opts.granularity {
+        Granularity::Monthly => 1,
+        Granularity::Daily | Granularity::Weekly => caps.get("day").map(|d| *d as u32).unwrap_or(1),
+    };
+    NaiveDate::from_ymd_opt(y, m, day).ok_or(InvalidDate)
 }
 
 pub fn datetime_str_to_iso8601_jst(s: &str) -> String {
@@ -245,3 +1739,625 @@ pub fn sanitize_id(s: &str) -> String {
     let hash = hasher.finish();
     format!("{}_{:08x}", base, hash)
 }
+
+/// Classify every normalized path present in either snapshot.
+/// Size changes take precedence over content and modified-time changes. When both
+/// sides carry a content hash, a matching hash means unchanged even if the file was touched.
+/// Removed/added pairs that look like the same file are reported once as `Renamed`.
+pub fn diff_snapshots(prev: &Snapshot, curr: &Snapshot) -> Vec<DiffEntry> {
+    let prev_map: BTreeMap<&str, &FileInfo> = prev
+        .files
+        .iter()
+        .map(|f| (f.normalized_rel_path.as_str(), f))
+        .collect();
+    let curr_map: BTreeMap<&str, &FileInfo> = curr
+        .files
+        .iter()
+        .map(|f| (f.normalized_rel_path.as_str(), f))
+        .collect();
+
+    let keys: BTreeSet<&str> = prev_map.keys().chain(curr_map.keys()).copied().collect();
+
+    let entries = keys
+        .into_iter()
+        .map(|key| {
+            let p = prev_map.get(key).copied();
+            let c = curr_map.get(key).copied();
+            let kind = match (p, c) {
+                (None, Some(_)) => ChangeKind::Added,
+                (Some(_), None) => ChangeKind::Removed,
+                (Some(p), Some(c)) if p.size != c.size => ChangeKind::SizeChanged,
+                (Some(FileInfo { content_hash: Some(ph), .. }), Some(FileInfo { content_hash: Some(ch), .. })) => {
+                    if ph != ch {
+                        ChangeKind::ContentChanged
+                    } else {
+                        ChangeKind::Unchanged
+                    }
+                }
+                (Some(p), Some(c)) if p.modified != c.modified => ChangeKind::MtimeChanged,
+                _ => ChangeKind::Unchanged,
+            };
+            DiffEntry {
+                normalized_rel_path: key.to_string(),
+                prev_date_str: prev.date_str.clone(),
+                date_str: curr.date_str.clone(),
+                kind,
+                previous: p.cloned(),
+                current: c.cloned(),
+                renamed_from: None,
+            }
+        })
+        .collect::<Vec<_>>();
+
+    pair_renames(entries)
+}
+
+/// Minimum file-name similarity for pairing files without comparable content hashes.
+pub const RENAME_NAME_SIMILARITY: f64 = 0.6;
+
+/// Similarity of two names in [0, 1], based on character-level edit distance.
+pub fn name_similarity(a: &str, b: &str) -> f64 {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    let max_len = a.len().max(b.len());
+    if max_len == 0 {
+        return 1.0;
+    }
+
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.iter().enumerate() {
+        let mut diag = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let cost = if ca == cb { diag } else { diag + 1 };
+            diag = row[j + 1];
+            row[j + 1] = cost.min(row[j] + 1).min(row[j + 1] + 1);
+        }
+    }
+    1.0 - row[b.len()] as f64 / max_len as f64
+}
+
+fn file_name_of(rel_path: &str) -> &str {
+    rel_path.rsplit('/').next().unwrap_or(rel_path)
+}
+
+/// Merge removed/added pairs that look like the same file into `Renamed` entries.
+/// Both sides need similar file names; identical content hashes pair first, and when either
+/// side has no hash, equal size is accepted instead. Empty files are never paired, since any
+/// two of them look identical.
+fn pair_renames(entries: Vec<DiffEntry>) -> Vec<DiffEntry> {
+    let removed: Vec<usize> = (0..entries.len())
+        .filter(|&i| entries[i].kind == ChangeKind::Removed)
+        .collect();
+    let added: Vec<usize> = (0..entries.len())
+        .filter(|&i| entries[i].kind == ChangeKind::Added)
+        .collect();
+    if removed.is_empty() || added.is_empty() {
+        return entries;
+    }
+
+    // (score, removed index, added index); exact hash matches score above any name match
+    let mut candidates: Vec<(f64, usize, usize)> = Vec::new();
+    for &r in &removed {
+        let old = entries[r].previous.as_ref().unwrap();
+        for &a in &added {
+            let new = entries[a].current.as_ref().unwrap();
+            if old.size == 0 || new.size == 0 {
+                continue;
+            }
+            let name_score = name_similarity(
+                file_name_of(&old.normalized_rel_path),
+                file_name_of(&new.normalized_rel_path),
+            );
+            if name_score < RENAME_NAME_SIMILARITY {
+                continue;
+            }
+            match (&old.content_hash, &new.content_hash) {
+                (Some(oh), Some(nh)) => {
+                    if oh == nh {
+                        candidates.push((2.0 + name_score, r, a));
+                    }
+                }
+                _ => {
+                    if old.size == new.size {
+                        candidates.push((name_score, r, a));
+                    }
+                }
+            }
+        }
+    }
+    candidates.sort_by(|x, y| y.0.total_cmp(&x.0));
+
+    let mut paired: Vec<(usize, usize)> = Vec::new();
+    for (_, r, a) in candidates {
+        if paired.iter().any(|&(pr, pa)| pr == r || pa == a) {
+            continue;
+        }
+        paired.push((r, a));
+    }
+
+    let mut entries: Vec<Option<DiffEntry>> = entries.into_iter().map(Some).collect();
+    for (r, a) in paired {
+        let old = entries[r].take().unwrap();
+        let new = entries[a].as_mut().unwrap();
+        new.kind = ChangeKind::Renamed;
+        new.renamed_from = Some(old.normalized_rel_path);
+        new.previous = old.previous;
+    }
+    entries.into_iter().flatten().collect()
+}
+
+/// Diff each snapshot against the one before it. Snapshots must be in chronological order.
+pub fn diff_consecutive(snapshots: &[Snapshot]) -> Vec<DiffEntry> {
+    snapshots
+        .windows(2)
+        .flat_map(|w| diff_snapshots(&w[0], &w[1]))
+        .collect()
+}
+
+/// Which snapshots a normalized path appears in.
+#[derive(Debug, Clone, Serialize)]
+pub struct Presence {
+    pub normalized_rel_path: String,
+    /// One flag per month of the matrix
+    pub present: Vec<bool>,
+    pub missing_months: Vec<String>,
+}
+
+/// Normalized path x month presence over all snapshots.
+#[derive(Debug, Clone, Serialize)]
+pub struct PresenceMatrix {
+    /// Snapshot months in chronological order
+    pub months: Vec<String>,
+    /// Sorted by normalized path
+    pub rows: Vec<Presence>,
+}
+
+impl PresenceMatrix {
+    /// Rows missing in at least one month.
+    pub fn gaps(&self) -> impl Iterator<Item = &Presence> {
+        self.rows.iter().filter(|r| !r.missing_months.is_empty())
+    }
+}
+
+pub fn presence_matrix(snapshots: &[Snapshot]) -> PresenceMatrix {
+    let mut months: Vec<String> = snapshots.iter().map(|s| s.date_str.clone()).collect();
+    months.sort();
+    months.dedup();
+
+    let mut seen: BTreeMap<&str, BTreeSet<&str>> = BTreeMap::new();
+    for snap in snapshots {
+        for f in &snap.files {
+            seen.entry(f.normalized_rel_path.as_str())
+                .or_default()
+                .insert(snap.date_str.as_str());
+        }
+    }
+
+    let rows = seen
+        .into_iter()
+        .map(|(path, in_months)| {
+            let present: Vec<bool> = months.iter().map(|m| in_months.contains(m.as_str())).collect();
+            let missing_months = months
+                .iter()
+                .zip(&present)
+                .filter(|(_, p)| !**p)
+                .map(|(m, _)| m.clone())
+                .collect();
+            Presence {
+                normalized_rel_path: path.to_string(),
+                present,
+                missing_months,
+            }
+        })
+        .collect();
+
+    PresenceMatrix { months, rows }
+}
+
+/// Default size change (in percent of the previous month) flagged as an anomaly.
+pub const DEFAULT_ANOMALY_PERCENT: f64 = 50.0;
+
+/// Thresholds for flagging size changes between consecutive months of one series.
+/// A change is flagged when it reaches either threshold that is set.
+#[derive(Debug, Clone, Copy)]
+pub struct AnomalyThresholds {
+    /// Relative change in percent of the previous size
+    pub percent: Option<f64>,
+    /// Absolute change in bytes
+    pub bytes: Option<u64>,
+}
+
+impl Default for AnomalyThresholds {
+    fn default() -> Self {
+        Self {
+            percent: Some(DEFAULT_ANOMALY_PERCENT),
+            bytes: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Severity {
+    Warning,
+    Critical,
+}
+
+impl Severity {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Severity::Warning => "warning",
+            Severity::Critical => "critical",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AnomalyKind {
+    SizeJump,
+    SizeDrop,
+    /// Non-empty last month, zero bytes now
+    ZeroBytes,
+}
+
+impl AnomalyKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AnomalyKind::SizeJump => "size_jump",
+            AnomalyKind::SizeDrop => "size_drop",
+            AnomalyKind::ZeroBytes => "zero_bytes",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SizeAnomaly {
+    pub normalized_rel_path: String,
+    pub prev_date_str: String,
+    pub date_str: String,
+    pub kind: AnomalyKind,
+    pub severity: Severity,
+    pub prev_size: u64,
+    pub size: u64,
+}
+
+impl SizeAnomaly {
+    /// Relative change; None when the previous size was zero.
+    pub fn delta_percent(&self) -> Option<f64> {
+        if self.prev_size == 0 {
+            return None;
+        }
+        Some((self.size as f64 - self.prev_size as f64) / self.prev_size as f64 * 100.0)
+    }
+}
+
+/// Flag size jumps, drops and files becoming empty in one series of the same normalized path.
+/// Changes reaching twice a threshold, and files becoming empty, are critical.
+pub fn detect_size_anomalies(series: &[FileInfo], thresholds: &AnomalyThresholds) -> Vec<SizeAnomaly> {
+    let mut sorted: Vec<&FileInfo> = series.iter().collect();
+    sorted.sort_by(|a, b| a.date_str.cmp(&b.date_str));
+
+    sorted
+        .windows(2)
+        .filter_map(|w| {
+            let (prev, curr) = (w[0], w[1]);
+            let anomaly = |kind, severity| SizeAnomaly {
+                normalized_rel_path: curr.normalized_rel_path.clone(),
+                prev_date_str: prev.date_str.clone(),
+                date_str: curr.date_str.clone(),
+                kind,
+                severity,
+                prev_size: prev.size,
+                size: curr.size,
+            };
+
+            if curr.size == 0 && prev.size > 0 {
+                return Some(anomaly(AnomalyKind::ZeroBytes, Severity::Critical));
+            }
+
+            let delta = curr.size.abs_diff(prev.size);
+            // How many times the change exceeds the strictest threshold it reaches
+            let mut ratio: f64 = 0.0;
+            if let Some(pct) = thresholds.percent {
+                if pct > 0.0 && prev.size > 0 {
+                    ratio = ratio.max(delta as f64 / prev.size as f64 * 100.0 / pct);
+                }
+            }
+            if let Some(bytes) = thresholds.bytes {
+                if bytes > 0 {
+                    ratio = ratio.max(delta as f64 / bytes as f64);
+                }
+            }
+            if ratio < 1.0 {
+                return None;
+            }
+
+            let kind = if curr.size > prev.size {
+                AnomalyKind::SizeJump
+            } else {
+                AnomalyKind::SizeDrop
+            };
+            let severity = if ratio >= 2.0 {
+                Severity::Critical
+            } else {
+                Severity::Warning
+            };
+            Some(anomaly(kind, severity))
+        })
+        .collect()
+}
+
+/// Parse "YYYY-MM-DD", or "YYYY-MM" as the first day of that month.
+pub fn parse_snapshot_date(s: &str) -> Result<NaiveDate, String> {
+    let s = s.trim();
+    NaiveDate::parse_from_str(s, "%Y-%m-%d")
+        .or_else(|_| NaiveDate::parse_from_str(&format!("{}-01", s), "%Y-%m-%d"))
+        .map_err(|_| format!("invalid date '{}' (expected YYYY-MM-DD or YYYY-MM)", s))
+}
+
+/// How a normalized path in one month compares to the baseline month.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BaselineStatus {
+    /// In the baseline, absent this month
+    Missing,
+    /// Absent from the baseline, present this month
+    New,
+    SizeDrift,
+    Same,
+}
+
+impl BaselineStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            BaselineStatus::Missing => "missing",
+            BaselineStatus::New => "new",
+            BaselineStatus::SizeDrift => "size_drift",
+            BaselineStatus::Same => "same",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct BaselineEntry {
+    pub normalized_rel_path: String,
+    pub status: BaselineStatus,
+    pub baseline_size: Option<u64>,
+    pub size: Option<u64>,
+}
+
+impl BaselineEntry {
+    pub fn delta_bytes(&self) -> Option<i64> {
+        Some(self.size? as i64 - self.baseline_size? as i64)
+    }
+
+    /// Size drift relative to the baseline size; None when the baseline file is empty.
+    pub fn delta_percent(&self) -> Option<f64> {
+        let base = self.baseline_size?;
+        if base == 0 {
+            return None;
+        }
+        Some(self.delta_bytes()? as f64 / base as f64 * 100.0)
+    }
+}
+
+/// One month compared against the baseline month.
+#[derive(Debug, Clone)]
+pub struct BaselineDelta {
+    pub baseline_date_str: String,
+    pub date_str: String,
+    pub entries: Vec<BaselineEntry>,
+}
+
+impl BaselineDelta {
+    pub fn count(&self, status: BaselineStatus) -> usize {
+        self.entries.iter().filter(|e| e.status == status).count()
+    }
+}
+
+/// Compare every snapshot except the baseline itself against the baseline's file set.
+/// Matching is by normalized path only, so renamed files show up as missing + new.
+pub fn compare_to_baseline(baseline: &Snapshot, snapshots: &[Snapshot]) -> Vec<BaselineDelta> {
+    let base_map: BTreeMap<&str, &FileInfo> = baseline
+        .files
+        .iter()
+        .map(|f| (f.normalized_rel_path.as_str(), f))
+        .collect();
+
+    snapshots
+        .iter()
+        .filter(|snap| snap.date_str != baseline.date_str)
+        .map(|snap| {
+            let map: BTreeMap<&str, &FileInfo> = snap
+                .files
+                .iter()
+                .map(|f| (f.normalized_rel_path.as_str(), f))
+                .collect();
+            let keys: BTreeSet<&str> = base_map.keys().chain(map.keys()).copied().collect();
+            let entries = keys
+                .into_iter()
+                .map(|key| {
+                    let b = base_map.get(key).map(|f| f.size);
+                    let c = map.get(key).map(|f| f.size);
+                    let status = match (b, c) {
+                        (Some(_), None) => BaselineStatus::Missing,
+                        (None, Some(_)) => BaselineStatus::New,
+                        (Some(b), Some(c)) if b != c => BaselineStatus::SizeDrift,
+                        _ => BaselineStatus::Same,
+                    };
+                    BaselineEntry {
+                        normalized_rel_path: key.to_string(),
+                        status,
+                        baseline_size: b,
+                        size: c,
+                    }
+                })
+                .collect();
+            BaselineDelta {
+                baseline_date_str: baseline.date_str.clone(),
+                date_str: snap.date_str.clone(),
+                entries,
+            }
+        })
+        .collect()
+}
+
+/// Extensions eligible for line-level text diffs.
+pub const TEXT_DIFF_EXTENSIONS: &[&str] = &["txt", "csv", "tsv", "log"];
+
+/// Files larger than this are not read for text diffs.
+pub const TEXT_DIFF_MAX_BYTES: u64 = 8 * 1024 * 1024;
+
+pub fn is_text_like(rel_path: &str) -> bool {
+    Path::new(rel_path)
+        .extension()
+        .map(|e| {
+            let e = e.to_string_lossy().to_lowercase();
+            TEXT_DIFF_EXTENSIONS.contains(&e.as_str())
+        })
+        .unwrap_or(false)
+}
+
+/// Decode file bytes: a BOM wins, then UTF-16 recognised without one (see `sniff_utf16`),
+/// then UTF-8 if valid, otherwise Shift_JIS.
+pub fn decode_text(bytes: &[u8]) -> String {
+    if let Some((enc, bom_len)) = Encoding::for_bom(bytes) {
+        return enc.decode_without_bom_handling(&bytes[bom_len..]).0.into_owned();
+    }
+    if let Some(enc) = sniff_utf16(bytes) {
+        return enc.decode_without_bom_handling(bytes).0.into_owned();
+    }
+    match std::str::from_utf8(bytes) {
+        Ok(s) => s.to_string(),
+        Err(_) => SHIFT_JIS.decode_without_bom_handling(bytes).0.into_owned(),
+    }
+}
+
+/// UTF-16 without a BOM: NUL bytes only on one side of the code units, as the ASCII
+/// delimiters and line breaks of an exported CSV leave them. UTF-8 and Shift_JIS text
+/// has no NUL bytes at all.
+fn sniff_utf16(bytes: &[u8]) -> Option<&'static Encoding> {
+    if !bytes.len().is_multiple_of(2) {
+        return None;
+    }
+    let sample = &bytes[..bytes.len().min(4096)];
+    let nul_at = |parity| sample.iter().skip(parity).step_by(2).any(|b| *b == 0);
+    match (nul_at(0), nul_at(1)) {
+        (false, true) => Some(UTF_16LE),
+        (true, false) => Some(UTF_16BE),
+        _ => None,
+    }
+}
+
+/// Unified diff between two versions of a text file in consecutive snapshots.
+#[derive(Debug, Clone, Serialize)]
+pub struct TextDiffEntry {
+    pub normalized_rel_path: String,
+    pub prev_date_str: String,
+    pub date_str: String,
+    pub lines_added: usize,
+    pub lines_removed: usize,
+    pub unified: String,
+}
+
+/// Line diff of two texts: (lines added, lines removed, unified diff with the given headers).
+pub fn text_diff(old: &str, new: &str, old_label: &str, new_label: &str) -> (usize, usize, String) {
+    use similar::{ChangeTag, TextDiff};
+
+    let diff = TextDiff::from_lines(old, new);
+    let mut added = 0;
+    let mut removed = 0;
+    for change in diff.iter_all_changes() {
+        match change.tag() {
+            ChangeTag::Insert => added += 1,
+            ChangeTag::Delete => removed += 1,
+            ChangeTag::Equal => {}
+        }
+    }
+    let unified = diff
+        .unified_diff()
+        .context_radius(3)
+        .header(old_label, new_label)
+        .to_string();
+    (added, removed, unified)
+}
+
+/// Text diffs for every text-like file present on both sides of a diff entry.
+/// Files without line changes, unreadable files and files over `TEXT_DIFF_MAX_BYTES` are skipped.
+pub fn text_diffs(snapshots: &[Snapshot], diffs: &[DiffEntry]) -> Vec<TextDiffEntry> {
+    let roots: BTreeMap<&str, &Path> = snapshots
+        .iter()
+        .map(|s| (s.date_str.as_str(), s.root.as_path()))
+        .collect();
+    let read = |root: Option<&&Path>, info: &FileInfo| -> Option<String> {
+        if info.size > TEXT_DIFF_MAX_BYTES {
+            return None;
+        }
+        fs::read(root?.join(&info.rel_path)).ok().map(|b| decode_text(&b))
+    };
+
+    diffs
+        .iter()
+        .filter_map(|d| {
+            let (prev, curr) = (d.previous.as_ref()?, d.current.as_ref()?);
+            if !is_text_like(&curr.rel_path) {
+                return None;
+            }
+            let old = read(roots.get(d.prev_date_str.as_str()), prev)?;
+            let new = read(roots.get(d.date_str.as_str()), curr)?;
+            let (lines_added, lines_removed, unified) = text_diff(
+                &old,
+                &new,
+                &format!("a/{}/{}", d.prev_date_str, prev.rel_path),
+                &format!("b/{}/{}", d.date_str, curr.rel_path),
+            );
+            if lines_added == 0 && lines_removed == 0 {
+                return None;
+            }
+            Some(TextDiffEntry {
+                normalized_rel_path: d.normalized_rel_path.clone(),
+                prev_date_str: d.prev_date_str.clone(),
+                date_str: d.date_str.clone(),
+                lines_added,
+                lines_removed,
+                unified,
+            })
+        })
+        .collect()
+}
+
+/// Writer that transcodes UTF-8 input to UTF-16LE.
+/// `encoding_rs` only decodes UTF-16, so its UTF-16LE encoder emits UTF-8.
+pub struct Utf16LeWriter<W: Write> {
+    inner: W,
+    // Trailing bytes of an incomplete UTF-8 sequence from the previous write
+    pending: Vec<u8>,
+}
+
+impl<W: Write> Utf16LeWriter<W> {
+    pub fn new(inner: W) -> Self {
+        Self { inner, pending: Vec::new() }
+    }
+}
+
+impl<W: Write> Write for Utf16LeWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.pending.extend_from_slice(buf);
+        let valid_up_to = match std::str::from_utf8(&self.pending) {
+            Ok(_) => self.pending.len(),
+            Err(e) if e.error_len().is_none() => e.valid_up_to(),
+            Err(e) => return Err(io::Error::new(io::ErrorKind::InvalidData, e)),
+        };
+        let text = std::str::from_utf8(&self.pending[..valid_up_to]).unwrap();
+        let bytes: Vec<u8> = text.encode_utf16().flat_map(|u| u.to_le_bytes()).collect();
+        self.inner.write_all(&bytes)?;
+        self.pending.drain(..valid_up_to);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
@@ -1,5 +1,5 @@
 // main.rs
-use chrono::NaiveDate;
+use chrono::{Local, NaiveDate};
 use clap::Parser;
 use std::{
     collections::{HashMap, BTreeMap},
@@ -7,7 +7,7 @@ use std::{
     io::{self, Write},
     path::{PathBuf, Path},
 };
-use encoding_rs::{SHIFT_JIS, UTF_16LE};
+use encoding_rs::SHIFT_JIS;
 use encoding_rs_rw::EncodingWriter;
 
 use serde::Serialize;
@@ -15,20 +15,48 @@ use serde_json::to_string as to_json;
 use tera::{Context, Tera};
 
 use monthly_file_diff::{
-    FileInfo, resolve_template, collect_files, extract_dates_from_template,
-    datetime_str_to_iso8601_jst, sanitize_id
+    FileInfo, Snapshot, DiffEntry, ChangeKind, HashAlgorithm, Utf16LeWriter,
+    CollectOptions, DateNormalizer, DiscoverOptions, FolderIssueKind, Granularity, MatchPolicy, PathMapping, Rollup,
+    BaselineDelta, BaselineStatus, TextDiffEntry, PresenceMatrix, AnomalyThresholds, SizeAnomaly, Severity,
+    SchemaDiff, SchemaDrift, WorkbookChange, WorkbookDiff, WorkbookSnapshot,
+    DEFAULT_FISCAL_START_MONTH, DEFAULT_ROW_JUMP_PERCENT, DEFAULT_ANOMALY_PERCENT,
+    resolve_path, resolve_template_with, expand_env, map_path_prefix, parse_path_mappings,
+    parse_normalization_rules, trace_normalization_with, disambiguate_collisions,
+    roll_up_dates, date_range, last_periods, discover_dates, collect_files_with,
+    compare_to_baseline, parse_snapshot_date, text_diffs, inspect_workbooks, workbook_diffs,
+    schema_diffs, detect_size_anomalies, presence_matrix, diff_consecutive, datetime_str_to_iso8601_jst, sanitize_id
 };
 
 #[derive(Parser, Debug)]
 struct Args {
-    /// Template path like D:\data\参照{yyyy}年_{mm}月データ\Main
+    /// Template path like D:\data\参照{yyyy}年_{mm}月データ\Main; ~, $VAR, ${VAR} and %VAR% are expanded
     #[arg(short, long)]
     template: String,
 
+    /// Rewrite a template prefix, e.g. "D:=/mnt/d" or "\\server\share=/mnt/share" (repeatable)
+    #[arg(long = "path-map")]
+    path_maps: Vec<PathMapping>,
+
+    /// File with one FROM=TO prefix mapping per line, applied after --path-map
+    #[arg(long)]
+    path_map_file: Option<PathBuf>,
+
     /// Optional date list (e.g., 2024-12-01,2025-01-01)
     #[arg(short, long)]
     dates: Option<String>,
 
+    /// Expect every period from this date (e.g., 2024-04) instead of discovering folders
+    #[arg(long, value_parser = parse_snapshot_date, conflicts_with = "dates")]
+    from: Option<NaiveDate>,
+
+    /// Last expected period for --from/--last (default: today)
+    #[arg(long, value_parser = parse_snapshot_date, conflicts_with = "dates")]
+    to: Option<NaiveDate>,
+
+    /// Expect the last N periods, ending at --to (default: today)
+    #[arg(long, conflicts_with_all = ["dates", "from"])]
+    last: Option<usize>,
+
     /// Output encoding for CSV: "utf8", "shift_jis", or "utf16le". Default is utf8.
     #[arg(short, long)]
     encoding: Option<String>,
@@ -44,6 +72,82 @@ struct Args {
     /// Whether to auto-detect yyyy/mm patterns in file names (default: true)
     #[arg(long, default_value_t = true, action = clap::ArgAction::Set)]
     detect_filename_dates: bool,
+
+    /// Also date-normalize directory names at these depths below the folder, e.g. "2" for
+    /// Main/2024-12/Detail.csv (comma-separated, default: file names only)
+    #[arg(long, value_delimiter = ',')]
+    normalize_dirs: Vec<usize>,
+
+    /// Also recognise file name dates up to N months before or after the folder month, e.g.
+    /// Closing_02-2025.xlsx in the March folder as {mm-1}-{yyyy-1m} (default: 0, off)
+    #[arg(long, default_value_t = 0)]
+    month_window: u32,
+
+    /// File of "REGEX => REPLACEMENT" rules applied to normalized paths after the date
+    /// normalization (e.g. to drop "_v3" or export timestamps)
+    #[arg(long)]
+    normalize_rules: Option<PathBuf>,
+
+    /// Print how each file path was normalized (date shape and rewriting rules) instead of
+    /// writing the reports
+    #[arg(long, default_value_t = false)]
+    explain_normalization: bool,
+
+    /// Hash file contents to detect real changes: "sha256" or "xxh3" (default: off)
+    #[arg(long)]
+    hash: Option<HashAlgorithm>,
+
+    /// Match date folders against the whole folder name during discovery (default: false)
+    #[arg(long, default_value_t = false)]
+    strict: bool,
+
+    /// Folder to use when a */? template segment matches several: "latest-mtime",
+    /// "lexical-last" or "error" (default: latest-mtime)
+    #[arg(long, default_value = "latest-mtime")]
+    match_policy: MatchPolicy,
+
+    /// Snapshot granularity: "daily", "weekly" or "monthly" (default: monthly)
+    #[arg(long, default_value = "monthly")]
+    granularity: Granularity,
+
+    /// First month of the fiscal year for {fy}/{q} and --rollup (default: 4, April)
+    #[arg(long, default_value_t = DEFAULT_FISCAL_START_MONTH, value_parser = clap::value_parser!(u32).range(1..=12))]
+    fiscal_start_month: u32,
+
+    /// Roll snapshots up to "quarter" or "fiscal-year", keeping the last snapshot of each period
+    #[arg(long)]
+    rollup: Option<Rollup>,
+
+    /// Compare every snapshot against this reference (e.g., 2024-12, or 2024-12-02 for
+    /// daily/weekly snapshots)
+    #[arg(long, value_parser = parse_snapshot_date)]
+    baseline: Option<NaiveDate>,
+
+    /// Write unified diffs of text-like files (.txt/.csv/.tsv/.log) between consecutive
+    /// months to this file; also embeds them in the HTML report (default: off)
+    #[arg(long, default_value = "")]
+    text_diff_file: String,
+
+    /// Inspect .xlsx workbooks (sheets, used ranges, row counts, defined names) and
+    /// report structural changes between months (default: false)
+    #[arg(long, default_value_t = false)]
+    inspect_xlsx: bool,
+
+    /// Parse .csv/.tsv headers and row counts and report schema drift between months (default: false)
+    #[arg(long, default_value_t = false)]
+    schema_drift: bool,
+
+    /// Row-count change (percent of the previous month) reported as a jump by --schema-drift
+    #[arg(long, default_value_t = DEFAULT_ROW_JUMP_PERCENT)]
+    row_jump_percent: f64,
+
+    /// Flag size changes of at least this percent of the previous month (0 disables)
+    #[arg(long, default_value_t = DEFAULT_ANOMALY_PERCENT)]
+    anomaly_percent: f64,
+
+    /// Flag size changes of at least this many bytes (default: off)
+    #[arg(long)]
+    anomaly_bytes: Option<u64>,
 }
 
 
@@ -53,31 +157,318 @@ struct ChartFile {
     id: String,
     dates_json: String,
     sizes_json: String,
+    /// Point colour per date: anomalies are highlighted by severity
+    point_colors_json: String,
     created_json: String,
     modified_json: String,
     display_path: String,
     display_file_name: String,
+    changes: Vec<ChangeBadge>,
+    text_diffs: Vec<TextDiffEntry>,
+    /// Chart.js datasets of row counts per sheet, aligned with dates_json (xlsx only)
+    sheet_rows_json: Option<String>,
+    workbook_changes: Vec<WorkbookChangeRow>,
+    missing_months: Vec<String>,
 }
 
+#[derive(Serialize)]
+struct WorkbookChangeRow {
+    date: String,
+    #[serde(flatten)]
+    change: WorkbookChange,
+    row_delta: Option<i64>,
+}
 
+#[derive(Serialize)]
+struct SheetRowsDataset {
+    label: String,
+    data: Vec<Option<usize>>,
+}
 
-fn write_html_report_with_tera(
-    out_path: &Path,
-    grouped: &BTreeMap<String, Vec<FileInfo>>,
-) -> io::Result<()> {
-    let files: Vec<ChartFile> = grouped
+fn sheet_rows_datasets(
+    norm_rel_path: &str,
+    dates: &[String],
+    workbooks: &[WorkbookSnapshot],
+) -> Option<Vec<SheetRowsDataset>> {
+    let per_date: Vec<Option<&WorkbookSnapshot>> = dates
+        .iter()
+        .map(|date| {
+            workbooks
+                .iter()
+                .find(|w| w.normalized_rel_path == norm_rel_path && &w.date_str == date)
+        })
+        .collect();
+    if per_date.iter().all(Option::is_none) {
+        return None;
+    }
+
+    // Sheet names in order of first appearance
+    let mut names: Vec<&str> = Vec::new();
+    for w in per_date.iter().flatten() {
+        for sheet in &w.info.sheets {
+            if !names.contains(&sheet.name.as_str()) {
+                names.push(&sheet.name);
+            }
+        }
+    }
+
+    Some(
+        names
+            .into_iter()
+            .map(|name| SheetRowsDataset {
+                label: name.to_string(),
+                data: per_date
+                    .iter()
+                    .map(|w| {
+                        w.and_then(|w| w.info.sheets.iter().find(|s| s.name == name))
+                            .map(|s| s.row_count)
+                    })
+                    .collect(),
+            })
+            .collect(),
+    )
+}
+
+#[derive(Serialize)]
+struct ChangeBadge {
+    date: String,
+    kind: ChangeKind,
+    renamed_from: Option<String>,
+}
+
+#[derive(Serialize)]
+struct ChangeRow {
+    name: String,
+    kind: ChangeKind,
+    renamed_from: Option<String>,
+    prev_size: Option<u64>,
+    size: Option<u64>,
+    size_delta: Option<i64>,
+    /// "same" / "differs" when both sides were hashed
+    content: Option<&'static str>,
+}
+
+/// Changes between one snapshot and the one before it.
+#[derive(Serialize)]
+struct PeriodSummary {
+    prev_date: String,
+    date: String,
+    added: usize,
+    removed: usize,
+    size_changed: usize,
+    content_changed: usize,
+    mtime_changed: usize,
+    unchanged: usize,
+    renamed: usize,
+    /// Everything except unchanged entries
+    rows: Vec<ChangeRow>,
+}
+
+fn summarize_periods(diffs: &[DiffEntry]) -> Vec<PeriodSummary> {
+    let mut out: Vec<PeriodSummary> = Vec::new();
+    for d in diffs {
+        let needs_new = out
+            .last()
+            .map(|s| s.date != d.date_str || s.prev_date != d.prev_date_str)
+            .unwrap_or(true);
+        if needs_new {
+            out.push(PeriodSummary {
+                prev_date: d.prev_date_str.clone(),
+                date: d.date_str.clone(),
+                added: 0,
+                removed: 0,
+                size_changed: 0,
+                content_changed: 0,
+                mtime_changed: 0,
+                unchanged: 0,
+                renamed: 0,
+                rows: Vec::new(),
+            });
+        }
+        let summary = out.last_mut().unwrap();
+        match d.kind {
+            ChangeKind::Added => summary.added += 1,
+            ChangeKind::Removed => summary.removed += 1,
+            ChangeKind::SizeChanged => summary.size_changed += 1,
+            ChangeKind::ContentChanged => summary.content_changed += 1,
+            ChangeKind::MtimeChanged => summary.mtime_changed += 1,
+            ChangeKind::Unchanged => summary.unchanged += 1,
+            ChangeKind::Renamed => summary.renamed += 1,
+        }
+        if d.kind != ChangeKind::Unchanged {
+            summary.rows.push(ChangeRow {
+                name: d.normalized_rel_path.clone(),
+                kind: d.kind,
+                renamed_from: d.renamed_from.clone(),
+                prev_size: d.previous.as_ref().map(|f| f.size),
+                size: d.current.as_ref().map(|f| f.size),
+                size_delta: d.size_delta(),
+                content: d
+                    .content_differs()
+                    .map(|differs| if differs { "differs" } else { "same" }),
+            });
+        }
+    }
+    out
+}
+
+
+
+#[derive(Serialize)]
+struct BaselineRow {
+    name: String,
+    status: BaselineStatus,
+    baseline_size: Option<u64>,
+    size: Option<u64>,
+    delta_bytes: Option<i64>,
+    delta_percent: Option<String>,
+}
+
+/// One month compared against the baseline month.
+#[derive(Serialize)]
+struct BaselineMonth {
+    date: String,
+    missing: usize,
+    new: usize,
+    size_drift: usize,
+    same: usize,
+    /// Everything except files identical in size to the baseline
+    rows: Vec<BaselineRow>,
+}
+
+fn summarize_baseline(deltas: &[BaselineDelta]) -> Vec<BaselineMonth> {
+    deltas
+        .iter()
+        .map(|delta| BaselineMonth {
+            date: delta.date_str.clone(),
+            missing: delta.count(BaselineStatus::Missing),
+            new: delta.count(BaselineStatus::New),
+            size_drift: delta.count(BaselineStatus::SizeDrift),
+            same: delta.count(BaselineStatus::Same),
+            rows: delta
+                .entries
+                .iter()
+                .filter(|e| e.status != BaselineStatus::Same)
+                .map(|e| BaselineRow {
+                    name: e.normalized_rel_path.clone(),
+                    status: e.status,
+                    baseline_size: e.baseline_size,
+                    size: e.size,
+                    delta_bytes: e.delta_bytes(),
+                    delta_percent: e.delta_percent().map(|p| format!("{:+.2}%", p)),
+                })
+                .collect(),
+        })
+        .collect()
+}
+
+#[derive(Serialize)]
+struct SchemaDriftRow {
+    name: String,
+    prev_date: String,
+    date: String,
+    drifts: Vec<SchemaDrift>,
+    prev_columns: String,
+    columns: String,
+    prev_rows: usize,
+    rows: usize,
+    row_delta: i64,
+}
+
+fn schema_drift_rows(schema_changes: &[SchemaDiff]) -> Vec<SchemaDriftRow> {
+    schema_changes
+        .iter()
+        .map(|s| SchemaDriftRow {
+            name: s.normalized_rel_path.clone(),
+            prev_date: s.prev_date_str.clone(),
+            date: s.date_str.clone(),
+            drifts: s.drifts.clone(),
+            prev_columns: s.prev_schema.columns.join(", "),
+            columns: s.schema.columns.join(", "),
+            prev_rows: s.prev_schema.row_count,
+            rows: s.schema.row_count,
+            row_delta: s.row_delta(),
+        })
+        .collect()
+}
+
+/// Everything derived from the collected snapshots that the CSV and HTML outputs render.
+struct Report {
+    /// normalized_rel_path -> files, in stable order
+    grouped: BTreeMap<String, Vec<FileInfo>>,
+    diffs: Vec<DiffEntry>,
+    baseline: Vec<BaselineDelta>,
+    text_diffs: Vec<TextDiffEntry>,
+    workbooks: Vec<WorkbookSnapshot>,
+    workbook_changes: Vec<WorkbookDiff>,
+    schema_changes: Vec<SchemaDiff>,
+    /// Sorted by severity, most severe first
+    anomalies: Vec<SizeAnomaly>,
+    presence: PresenceMatrix,
+    /// Expected dates whose folder does not exist
+    missing_folders: Vec<MissingFolder>,
+    /// Folders skipped or matched ambiguously during discovery
+    folder_issues: Vec<FolderIssueRow>,
+    /// Every resolved date in chronological order, shared x-axis of all charts
+    axis: Vec<String>,
+}
+
+#[derive(Serialize)]
+struct FolderIssueRow {
+    kind: FolderIssueKind,
+    date: Option<String>,
+    path: String,
+}
+
+#[derive(Serialize)]
+struct MissingFolder {
+    date: String,
+    path: String,
+}
+
+#[derive(Serialize)]
+struct AnomalyRow {
+    #[serde(flatten)]
+    anomaly: SizeAnomaly,
+    delta_percent: Option<String>,
+}
+
+fn write_html_report_with_tera(out_path: &Path, report: &Report) -> io::Result<()> {
+    let files: Vec<ChartFile> = report
+        .grouped
         .iter()
         .map(|(norm_rel_path, infos)| {
-            // time series data
-            let dates: Vec<String> = infos.iter().map(|i| i.date_str.clone()).collect();
-            let sizes: Vec<u64> = infos.iter().map(|i| i.size).collect();
-            let created: Vec<String> = infos
+            // time series data on the shared axis; null where the file is missing,
+            // so the charts show a gap and line up across files
+            let dates = &report.axis;
+            let at: Vec<Option<&FileInfo>> = dates
+                .iter()
+                .map(|d| infos.iter().find(|i| &i.date_str == d))
+                .collect();
+            let sizes: Vec<Option<u64>> = at.iter().map(|i| i.map(|i| i.size)).collect();
+            let point_colors: Vec<&str> = dates
+                .iter()
+                .map(|date| {
+                    let severity = report
+                        .anomalies
+                        .iter()
+                        .filter(|a| &a.normalized_rel_path == norm_rel_path && &a.date_str == date)
+                        .map(|a| a.severity)
+                        .max();
+                    match severity {
+                        Some(Severity::Critical) => "red",
+                        Some(Severity::Warning) => "orange",
+                        None => "blue",
+                    }
+                })
+                .collect();
+            let created: Vec<Option<String>> = at
                 .iter()
-                .map(|i| datetime_str_to_iso8601_jst(&i.created))
+                .map(|i| i.map(|i| datetime_str_to_iso8601_jst(&i.created)))
                 .collect();
-            let modified: Vec<String> = infos
+            let modified: Vec<Option<String>> = at
                 .iter()
-                .map(|i| datetime_str_to_iso8601_jst(&i.modified))
+                .map(|i| i.map(|i| datetime_str_to_iso8601_jst(&i.modified)))
                 .collect();
 
             // display: split path & filename from normalized_rel_path
@@ -91,68 +482,539 @@ fn write_html_report_with_tera(
                 .map(|pp| pp.display().to_string().replace('\\', "/"))
                 .unwrap_or_else(|| ".".to_string());
 
+            let changes: Vec<ChangeBadge> = report
+                .diffs
+                .iter()
+                .filter(|d| &d.normalized_rel_path == norm_rel_path)
+                .map(|d| ChangeBadge {
+                    date: d.date_str.clone(),
+                    kind: d.kind,
+                    renamed_from: d.renamed_from.clone(),
+                })
+                .collect();
+
+            let file_text_diffs: Vec<TextDiffEntry> = report
+                .text_diffs
+                .iter()
+                .filter(|t| &t.normalized_rel_path == norm_rel_path)
+                .cloned()
+                .collect();
+
+            let missing_months: Vec<String> = report
+                .presence
+                .rows
+                .iter()
+                .find(|r| &r.normalized_rel_path == norm_rel_path)
+                .map(|r| r.missing_months.clone())
+                .unwrap_or_default();
+
+            let sheet_rows_json = sheet_rows_datasets(norm_rel_path, dates, &report.workbooks)
+                .map(|datasets| to_json(&datasets).unwrap());
+            let file_workbook_changes: Vec<WorkbookChangeRow> = report
+                .workbook_changes
+                .iter()
+                .filter(|w| &w.normalized_rel_path == norm_rel_path)
+                .flat_map(|w| {
+                    w.changes.iter().map(|c| WorkbookChangeRow {
+                        date: w.date_str.clone(),
+                        change: c.clone(),
+                        row_delta: c.row_delta(),
+                    })
+                })
+                .collect();
+
             ChartFile {
                 name: norm_rel_path.clone(),
                 id: sanitize_id(norm_rel_path),
                 dates_json: to_json(&dates).unwrap(),
                 sizes_json: to_json(&sizes).unwrap(),
+                point_colors_json: to_json(&point_colors).unwrap(),
                 created_json: to_json(&created).unwrap(),
                 modified_json: to_json(&modified).unwrap(),
                 display_path,
                 display_file_name,
+                changes,
+                text_diffs: file_text_diffs,
+                sheet_rows_json,
+                workbook_changes: file_workbook_changes,
+                missing_months,
             }
         })
         .collect();
 
     let tera = Tera::new("templates/**/*.html")
-        .map_err(|e| io::Error::new(io::ErrorKind::Other, e.to_string()))?;
+        .map_err(|e| io::Error::other(e.to_string()))?;
 
     let mut ctx = Context::new();
     ctx.insert("title", "File Info Charts");
     ctx.insert("files", &files);
+    let anomaly_rows: Vec<AnomalyRow> = report
+        .anomalies
+        .iter()
+        .map(|a| AnomalyRow {
+            anomaly: a.clone(),
+            delta_percent: a.delta_percent().map(|p| format!("{:+.1}%", p)),
+        })
+        .collect();
+    let count_of = |sev: Severity| report.anomalies.iter().filter(|a| a.severity == sev).count();
+    ctx.insert("anomalies", &anomaly_rows);
+    ctx.insert("critical_count", &count_of(Severity::Critical));
+    ctx.insert("warning_count", &count_of(Severity::Warning));
+    ctx.insert("periods", &summarize_periods(&report.diffs));
+    ctx.insert("presence", &report.presence);
+    ctx.insert("missing_folders", &report.missing_folders);
+    ctx.insert("folder_issues", &report.folder_issues);
+    ctx.insert("has_gaps", &report.presence.gaps().next().is_some());
+    ctx.insert(
+        "baseline_date",
+        &report.baseline.first().map(|d| d.baseline_date_str.clone()),
+    );
+    ctx.insert("baseline_months", &summarize_baseline(&report.baseline));
+    ctx.insert("schema_drifts", &schema_drift_rows(&report.schema_changes));
 
     let rendered = tera
         .render("report.html", &ctx)
-        .map_err(|e| io::Error::new(io::ErrorKind::Other, e.to_string()))?;
+        .map_err(|e| io::Error::other(e.to_string()))?;
 
     fs::write(out_path, rendered)
 }
 
 
+fn write_csv(writer: &mut dyn Write, report: &Report) -> io::Result<()> {
+    // (normalized_rel_path, date) -> diff against the previous snapshot
+    let diff_of: HashMap<(&str, &str), &DiffEntry> = report
+        .diffs
+        .iter()
+        .map(|d| ((d.normalized_rel_path.as_str(), d.date_str.as_str()), d))
+        .collect();
+    let anomaly_of: HashMap<(&str, &str), &SizeAnomaly> = report
+        .anomalies
+        .iter()
+        .map(|a| ((a.normalized_rel_path.as_str(), a.date_str.as_str()), a))
+        .collect();
+
+    writeln!(
+        writer,
+        "normalized_rel_path,date,actual_name,size,created,modified,rel_path,change,content_hash,content_changed,renamed_from,severity,anomaly"
+    )?;
+
+    for (norm_rel, infos) in &report.grouped {
+        for info in infos {
+            // The first snapshot has nothing to compare against
+            let diff = diff_of.get(&(norm_rel.as_str(), info.date_str.as_str()));
+            let change = diff.map(|d| d.kind.as_str()).unwrap_or("");
+            let content_changed = diff
+                .and_then(|d| d.content_differs())
+                .map(|b| b.to_string())
+                .unwrap_or_default();
+            let anomaly = anomaly_of.get(&(norm_rel.as_str(), info.date_str.as_str()));
+            writeln!(
+                writer,
+                "{},{},{},{},{},{},{},{},{},{},{},{},{}",
+                norm_rel,
+                info.date_str,
+                info.actual_name,
+                info.size,
+                info.created,
+                info.modified,
+                info.rel_path,
+                change,
+                info.content_hash.as_deref().unwrap_or(""),
+                content_changed,
+                diff.and_then(|d| d.renamed_from.as_deref()).unwrap_or(""),
+                anomaly.map(|a| a.severity.as_str()).unwrap_or(""),
+                anomaly.map(|a| a.kind.as_str()).unwrap_or("")
+            )?;
+        }
+        // Removed files have no row of their own in the month they disappear
+        for d in report
+            .diffs
+            .iter()
+            .filter(|d| d.kind == ChangeKind::Removed && &d.normalized_rel_path == norm_rel)
+        {
+            writeln!(writer, "{},{},,,,,,{},,,,,", norm_rel, d.date_str, d.kind.as_str())?;
+        }
+    }
+
+    // Expected dates without a folder
+    if !report.missing_folders.is_empty() {
+        writeln!(writer)?;
+        writeln!(writer, "missing_date,expected_path")?;
+        for missing in &report.missing_folders {
+            writeln!(writer, "{},{}", missing.date, missing.path)?;
+        }
+    }
+
+    // Folders skipped or matched ambiguously during discovery
+    if !report.folder_issues.is_empty() {
+        writeln!(writer)?;
+        writeln!(writer, "folder_issue,date,path")?;
+        for issue in &report.folder_issues {
+            writeln!(
+                writer,
+                "{},{},{}",
+                issue.kind.as_str(),
+                issue.date.as_deref().unwrap_or(""),
+                issue.path
+            )?;
+        }
+    }
+
+    // Presence matrix section, only when some file is missing in some month
+    if report.presence.gaps().next().is_some() {
+        writeln!(writer)?;
+        writeln!(
+            writer,
+            "normalized_rel_path,{},missing_months",
+            report.presence.months.join(",")
+        )?;
+        for row in &report.presence.rows {
+            let cells: Vec<&str> = row.present.iter().map(|p| if *p { "1" } else { "0" }).collect();
+            writeln!(
+                writer,
+                "{},{},{}",
+                row.normalized_rel_path,
+                cells.join(","),
+                row.missing_months.join(";")
+            )?;
+        }
+    }
+
+    // Baseline section: separated from the file list by a blank line
+    if !report.baseline.is_empty() {
+        writeln!(writer)?;
+        writeln!(
+            writer,
+            "baseline_date,date,normalized_rel_path,baseline_status,baseline_size,size,delta_bytes,delta_percent"
+        )?;
+        for delta in &report.baseline {
+            for e in &delta.entries {
+                writeln!(
+                    writer,
+                    "{},{},{},{},{},{},{},{}",
+                    delta.baseline_date_str,
+                    delta.date_str,
+                    e.normalized_rel_path,
+                    e.status.as_str(),
+                    e.baseline_size.map(|v| v.to_string()).unwrap_or_default(),
+                    e.size.map(|v| v.to_string()).unwrap_or_default(),
+                    e.delta_bytes().map(|v| v.to_string()).unwrap_or_default(),
+                    e.delta_percent().map(|v| format!("{:.2}", v)).unwrap_or_default()
+                )?;
+            }
+        }
+    }
+    // Workbook structure section
+    if !report.workbook_changes.is_empty() {
+        writeln!(writer)?;
+        writeln!(
+            writer,
+            "normalized_rel_path,prev_date,date,workbook_change,target,renamed_from,prev_rows,rows,row_delta,prev_range,range"
+        )?;
+        for w in &report.workbook_changes {
+            for c in &w.changes {
+                writeln!(
+                    writer,
+                    "{},{},{},{},{},{},{},{},{},{},{}",
+                    w.normalized_rel_path,
+                    w.prev_date_str,
+                    w.date_str,
+                    c.kind.as_str(),
+                    c.target,
+                    c.renamed_from.as_deref().unwrap_or(""),
+                    c.prev_rows.map(|v| v.to_string()).unwrap_or_default(),
+                    c.rows.map(|v| v.to_string()).unwrap_or_default(),
+                    c.row_delta().map(|v| v.to_string()).unwrap_or_default(),
+                    c.prev_range.as_deref().unwrap_or(""),
+                    c.range.as_deref().unwrap_or("")
+                )?;
+            }
+        }
+    }
+    // Schema drift section: one row per drift
+    if !report.schema_changes.is_empty() {
+        writeln!(writer)?;
+        writeln!(
+            writer,
+            "normalized_rel_path,prev_date,date,schema_drift,column,prev_rows,rows,row_delta"
+        )?;
+        for sc in &report.schema_changes {
+            for drift in &sc.drifts {
+                writeln!(
+                    writer,
+                    "{},{},{},{},{},{},{},{}",
+                    sc.normalized_rel_path,
+                    sc.prev_date_str,
+                    sc.date_str,
+                    drift.kind.as_str(),
+                    drift.column.as_deref().unwrap_or(""),
+                    sc.prev_schema.row_count,
+                    sc.schema.row_count,
+                    sc.row_delta()
+                )?;
+            }
+        }
+    }
+    writer.flush()
+}
+
+
+/// Keep files that normalize to the same path in one snapshot apart in every snapshot,
+/// warning with their actual paths.
+fn warn_collisions<'a>(snapshots: impl IntoIterator<Item = &'a mut Snapshot>) {
+    for collision in disambiguate_collisions(snapshots) {
+        eprintln!(
+            "Warning: {} files normalize to {} in {}: {} (kept apart as {}#...)",
+            collision.rel_paths.len(),
+            collision.normalized_rel_path,
+            collision.date_str,
+            collision.rel_paths.join(", "),
+            collision.normalized_rel_path
+        );
+    }
+}
+
+/// Print the normalization of every file in one snapshot: the date shape, each rule that
+/// rewrote the path, then the path the report uses (including collision suffixes).
+fn explain_normalization(files: &[FileInfo], date: NaiveDate, opts: &CollectOptions) {
+    println!("# {}", opts.period_label(date));
+    let normalizer = DateNormalizer::new(date, opts.granularity, opts.month_window);
+    for info in files {
+        let trace = trace_normalization_with(&info.rel_path, &normalizer, opts);
+        println!("{}", trace.rel_path);
+        match (trace.date_shape, trace.date_offset) {
+            (Some(shape), Some(offset)) if offset != 0 => {
+                println!("  date {} (offset {:+}): {}", shape, offset, trace.date_normalized)
+            }
+            (Some(shape), _) => println!("  date {}: {}", shape, trace.date_normalized),
+            (None, _) => println!("  date (none): {}", trace.date_normalized),
+        }
+        for step in &trace.steps {
+            println!("  rule {} `{}`: {}", step.rule + 1, opts.rules[step.rule].pattern, step.result);
+        }
+        println!("  => {}", info.normalized_rel_path);
+    }
+}
+
 fn main() -> io::Result<()> {
     let args = Args::parse();
 
+    let template = expand_env(&args.template).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    let mut mappings = args.path_maps.clone();
+    if let Some(path) = &args.path_map_file {
+        let table = parse_path_mappings(&fs::read_to_string(path)?)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        mappings.extend(table);
+    }
+    let template = map_path_prefix(&template, &mappings);
+
+    let mut folder_issues: Vec<FolderIssueRow> = Vec::new();
     let dates: Vec<NaiveDate> = if let Some(date_str) = args.dates {
         date_str
             .split(',')
             .filter_map(|s| NaiveDate::parse_from_str(s.trim(), "%Y-%m-%d").ok())
             .collect()
+    } else if args.from.is_some() || args.last.is_some() || args.to.is_some() {
+        let to = args.to.unwrap_or_else(|| Local::now().date_naive());
+        match (args.from, args.last) {
+            (Some(from), _) => date_range(from, to, args.granularity),
+            (None, Some(count)) => last_periods(to, count, args.granularity),
+            (None, None) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "--to needs --from or --last",
+                ))
+            }
+        }
     } else {
-        extract_dates_from_template(&args.template)
+        let discover_opts = DiscoverOptions {
+            granularity: args.granularity,
+            fiscal_start_month: args.fiscal_start_month,
+            strict: args.strict,
+            match_policy: args.match_policy,
+        };
+        let discovery = discover_dates(&template, &discover_opts);
+        for issue in &discovery.issues {
+            eprintln!("Warning: {} folder: {}", issue.kind.as_str(), issue.path.display());
+            folder_issues.push(FolderIssueRow {
+                kind: issue.kind,
+                date: issue.date.map(|d| args.granularity.label(d)),
+                path: issue.path.display().to_string(),
+            });
+        }
+        discovery.dates
+    };
+    let dates = match args.rollup {
+        Some(rollup) => roll_up_dates(&dates, rollup, args.fiscal_start_month),
+        None => dates,
+    };
+
+    let rules = match &args.normalize_rules {
+        Some(path) => parse_normalization_rules(&fs::read_to_string(path)?)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, format!("{}: {}", path.display(), e)))?,
+        None => Vec::new(),
+    };
+    let collect_opts = CollectOptions {
+        max_depth: args.max_depth,
+        detect_filename_dates: args.detect_filename_dates,
+        hash: args.hash,
+        granularity: args.granularity,
+        rollup: args.rollup,
+        fiscal_start_month: args.fiscal_start_month,
+        rules,
+        dir_depths: args.normalize_dirs.clone(),
+        month_window: args.month_window,
     };
 
-    // normalized_rel_path -> vec<FileInfo>
-    let mut grouped_by_norm_rel: HashMap<String, Vec<FileInfo>> = HashMap::new();
+    let mut snapshots: Vec<Snapshot> = Vec::new();
+    let mut missing_folders: Vec<MissingFolder> = Vec::new();
+    let mut collected_dates: Vec<NaiveDate> = Vec::new();
 
     for date in &dates {
-        let path = resolve_template(&args.template, *date);
+        let path = match resolve_path(&template, *date, args.fiscal_start_month, args.match_policy) {
+            Ok(path) => path,
+            Err(e) => {
+                eprintln!("Warning: skipping {}: {}", collect_opts.period_label(*date), e);
+                folder_issues.push(FolderIssueRow {
+                    kind: FolderIssueKind::Ambiguous,
+                    date: Some(collect_opts.period_label(*date)),
+                    path: resolve_template_with(&template, *date, args.fiscal_start_month)
+                        .display()
+                        .to_string(),
+                });
+                continue;
+            }
+        };
         if !path.exists() {
             eprintln!("Skipping missing path: {:?}", path);
+            missing_folders.push(MissingFolder {
+                date: collect_opts.period_label(*date),
+                path: path.display().to_string(),
+            });
             continue;
         }
-        for info in collect_files(
-            &path,
-            *date,
-            args.max_depth,
-            args.detect_filename_dates,
-        ) {
-            grouped_by_norm_rel
-                .entry(info.normalized_rel_path.clone())
-                .or_default()
-                .push(info);
+        snapshots.push(Snapshot {
+            date_str: collect_opts.period_label(*date),
+            files: collect_files_with(&path, *date, &collect_opts),
+            root: path,
+        });
+        collected_dates.push(*date);
+    }
+
+    // Baseline month outside the compared dates: collect it on its own
+    let mut extra_baseline: Option<Snapshot> = None;
+    if let Some(base_date) = args.baseline {
+        let base_str = collect_opts.period_label(base_date);
+        if !snapshots.iter().any(|s| s.date_str == base_str) {
+            let path = resolve_path(&template, base_date, args.fiscal_start_month, args.match_policy)?;
+            if !path.exists() {
+                return Err(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("Baseline path not found: {:?}", path),
+                ));
+            }
+            extra_baseline = Some(Snapshot {
+                date_str: base_str,
+                files: collect_files_with(&path, base_date, &collect_opts),
+                root: path,
+            });
+        }
+    }
+
+    // Collision suffixes must be the same in every month, so assign them across all snapshots
+    warn_collisions(snapshots.iter_mut().chain(extra_baseline.as_mut()));
+
+    if args.explain_normalization {
+        for (snap, date) in snapshots.iter().zip(&collected_dates) {
+            explain_normalization(&snap.files, *date, &collect_opts);
         }
+        return Ok(());
+    }
+
+    // normalized_rel_path -> vec<FileInfo>, stable ordering for CSV and HTML
+    let mut grouped: BTreeMap<String, Vec<FileInfo>> = BTreeMap::new();
+    for info in snapshots.iter().flat_map(|s| &s.files) {
+        grouped
+            .entry(info.normalized_rel_path.clone())
+            .or_default()
+            .push(info.clone());
+    }
+
+    let diffs = diff_consecutive(&snapshots);
+
+    // Chart axis: every resolved date, including those whose folder is missing
+    let mut axis: Vec<String> = dates.iter().map(|d| collect_opts.period_label(*d)).collect();
+    axis.sort();
+    axis.dedup();
+
+    let presence = presence_matrix(&snapshots);
+    for gap in presence.gaps() {
+        eprintln!(
+            "Warning: {} missing in {} of {} months: {}",
+            gap.normalized_rel_path,
+            gap.missing_months.len(),
+            presence.months.len(),
+            gap.missing_months.join(", ")
+        );
     }
 
+    let text_diff_entries: Vec<TextDiffEntry> = if args.text_diff_file.trim().is_empty() {
+        Vec::new()
+    } else {
+        text_diffs(&snapshots, &diffs)
+    };
+
+    let workbooks: Vec<WorkbookSnapshot> = if args.inspect_xlsx {
+        inspect_workbooks(&snapshots)
+    } else {
+        Vec::new()
+    };
+    let workbook_changes = workbook_diffs(&workbooks, &diffs);
+
+    let schema_changes: Vec<SchemaDiff> = if args.schema_drift {
+        schema_diffs(&snapshots, &diffs, args.row_jump_percent)
+    } else {
+        Vec::new()
+    };
+
+    let baseline_deltas: Vec<BaselineDelta> = match args.baseline {
+        Some(base_date) => {
+            let base_str = collect_opts.period_label(base_date);
+            let baseline = snapshots
+                .iter()
+                .find(|s| s.date_str == base_str)
+                .or(extra_baseline.as_ref())
+                .expect("baseline snapshot collected above");
+            compare_to_baseline(baseline, &snapshots)
+        }
+        None => Vec::new(),
+    };
+
+    let thresholds = AnomalyThresholds {
+        percent: Some(args.anomaly_percent),
+        bytes: args.anomaly_bytes,
+    };
+    let mut anomalies: Vec<SizeAnomaly> = grouped
+        .values()
+        .flat_map(|series| detect_size_anomalies(series, &thresholds))
+        .collect();
+    anomalies.sort_by_key(|a| std::cmp::Reverse(a.severity));
+
+    let report = Report {
+        grouped,
+        diffs,
+        baseline: baseline_deltas,
+        text_diffs: text_diff_entries,
+        workbooks,
+        workbook_changes,
+        schema_changes,
+        anomalies,
+        presence,
+        missing_folders,
+        folder_issues,
+        axis,
+    };
+
     // CSV output (same as before, but using the new grouping)
     let enc_label = args.encoding.as_deref().unwrap_or("utf8").to_lowercase();
     let mut writer: Box<dyn Write> = match enc_label.as_str() {
@@ -162,10 +1024,9 @@ fn main() -> io::Result<()> {
             Box::new(EncodingWriter::new(handle, SHIFT_JIS.new_encoder()))
         }
         "utf16le" => {
-            let encoder = UTF_16LE.new_encoder();
             let stdout = io::stdout();
             let handle = stdout.lock();
-            Box::new(EncodingWriter::new(handle, encoder))
+            Box::new(Utf16LeWriter::new(handle))
         }
         _ => {
             let stdout = io::stdout();
@@ -173,35 +1034,16 @@ fn main() -> io::Result<()> {
         }
     };
 
-    writeln!(
-        writer,
-        "normalized_rel_path,date,actual_name,size,created,modified,rel_path"
-    )?;
+    write_csv(&mut writer, &report)?;
 
-    for (norm_rel, infos) in &grouped_by_norm_rel {
-        for info in infos {
-            writeln!(
-                writer,
-                "{},{},{},{},{},{},{}",
-                norm_rel,
-                info.date_str,
-                info.actual_name,
-                info.size,
-                info.created,
-                info.modified,
-                info.rel_path
-            )?;
-        }
+    if !args.text_diff_file.trim().is_empty() {
+        let patch: String = report.text_diffs.iter().map(|t| t.unified.as_str()).collect();
+        fs::write(&args.text_diff_file, patch)?;
     }
-    writer.flush()?;
-
-    // stable ordering for HTML
-    let grouped: BTreeMap<String, Vec<FileInfo>> =
-        grouped_by_norm_rel.into_iter().collect();
 
     let html_path = PathBuf::from(&args.html_file);
     if !args.html_file.trim().is_empty() {
-        write_html_report_with_tera(&html_path, &grouped)?;
+        write_html_report_with_tera(&html_path, &report)?;
     }
 
     Ok(())
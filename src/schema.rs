@@ -0,0 +1,178 @@
+// schema.rs - Header and row-count drift of CSV/TSV data files
+use serde::Serialize;
+use std::{collections::HashMap, fs, io::{self, Read}, path::Path};
+
+use crate::{decode_text, DiffEntry, Snapshot, TEXT_DIFF_MAX_BYTES};
+
+/// Default row-count change (in percent of the previous month) flagged as a jump.
+pub const DEFAULT_ROW_JUMP_PERCENT: f64 = 50.0;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct CsvSchema {
+    pub columns: Vec<String>,
+    /// Data rows, excluding the header
+    pub row_count: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SchemaDriftKind {
+    ColumnAdded,
+    ColumnRemoved,
+    ColumnsReordered,
+    RowCountJump,
+}
+
+impl SchemaDriftKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SchemaDriftKind::ColumnAdded => "column_added",
+            SchemaDriftKind::ColumnRemoved => "column_removed",
+            SchemaDriftKind::ColumnsReordered => "columns_reordered",
+            SchemaDriftKind::RowCountJump => "row_count_jump",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SchemaDrift {
+    pub kind: SchemaDriftKind,
+    /// Affected column for added/removed columns
+    pub column: Option<String>,
+}
+
+/// Schema drift of one data file between consecutive snapshots.
+#[derive(Debug, Clone)]
+pub struct SchemaDiff {
+    pub normalized_rel_path: String,
+    pub prev_date_str: String,
+    pub date_str: String,
+    pub prev_schema: CsvSchema,
+    pub schema: CsvSchema,
+    pub drifts: Vec<SchemaDrift>,
+}
+
+impl SchemaDiff {
+    pub fn row_delta(&self) -> i64 {
+        self.schema.row_count as i64 - self.prev_schema.row_count as i64
+    }
+}
+
+/// Field delimiter for data files, or None when the file is not a CSV/TSV.
+pub fn data_file_delimiter(rel_path: &str) -> Option<u8> {
+    let ext = Path::new(rel_path).extension()?.to_string_lossy().to_lowercase();
+    match ext.as_str() {
+        "csv" => Some(b','),
+        "tsv" => Some(b'\t'),
+        _ => None,
+    }
+}
+
+/// Header row and data row count of already decoded delimited text.
+pub fn parse_csv_schema(text: &str, delimiter: u8) -> CsvSchema {
+    let mut reader = csv::ReaderBuilder::new()
+        .delimiter(delimiter)
+        .flexible(true)
+        .has_headers(true)
+        .from_reader(text.as_bytes());
+    let columns = reader
+        .headers()
+        .map(|h| h.iter().map(|c| c.trim().to_string()).collect())
+        .unwrap_or_default();
+    let row_count = reader.records().filter(|r| r.is_ok()).count();
+    CsvSchema { columns, row_count }
+}
+
+/// Read a CSV/TSV file, decoding UTF-8, UTF-16 (BOM) or Shift_JIS.
+/// Files over `TEXT_DIFF_MAX_BYTES` are refused, as for text diffs.
+pub fn read_csv_schema(path: &Path) -> io::Result<CsvSchema> {
+    let delimiter = data_file_delimiter(&path.to_string_lossy()).unwrap_or(b',');
+    let mut bytes = Vec::new();
+    // Read one byte past the limit so a file that grew since it was collected is caught too
+    fs::File::open(path)?
+        .take(TEXT_DIFF_MAX_BYTES + 1)
+        .read_to_end(&mut bytes)?;
+    if bytes.len() as u64 > TEXT_DIFF_MAX_BYTES {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{} is larger than {} bytes", path.display(), TEXT_DIFF_MAX_BYTES),
+        ));
+    }
+    Ok(parse_csv_schema(&decode_text(&bytes), delimiter))
+}
+
+/// Header changes and row-count jumps between two schemas.
+/// A change of at least `row_jump_percent` of the previous row count is a jump; any growth
+/// from zero rows is a jump as well.
+pub fn diff_schemas(prev: &CsvSchema, curr: &CsvSchema, row_jump_percent: f64) -> Vec<SchemaDrift> {
+    let mut drifts = Vec::new();
+
+    for col in curr.columns.iter().filter(|c| !prev.columns.contains(c)) {
+        drifts.push(SchemaDrift {
+            kind: SchemaDriftKind::ColumnAdded,
+            column: Some(col.clone()),
+        });
+    }
+    for col in prev.columns.iter().filter(|c| !curr.columns.contains(c)) {
+        drifts.push(SchemaDrift {
+            kind: SchemaDriftKind::ColumnRemoved,
+            column: Some(col.clone()),
+        });
+    }
+
+    // Compare the relative order of the columns both months share
+    let common_prev: Vec<&String> = prev.columns.iter().filter(|c| curr.columns.contains(c)).collect();
+    let common_curr: Vec<&String> = curr.columns.iter().filter(|c| prev.columns.contains(c)).collect();
+    if common_prev != common_curr {
+        drifts.push(SchemaDrift {
+            kind: SchemaDriftKind::ColumnsReordered,
+            column: None,
+        });
+    }
+
+    let (p, c) = (prev.row_count as f64, curr.row_count as f64);
+    let jumped = if prev.row_count == 0 {
+        curr.row_count > 0
+    } else {
+        ((c - p).abs() / p * 100.0) >= row_jump_percent
+    };
+    if jumped {
+        drifts.push(SchemaDrift {
+            kind: SchemaDriftKind::RowCountJump,
+            column: None,
+        });
+    }
+
+    drifts
+}
+
+/// Schema drift for every CSV/TSV file present on both sides of a diff entry.
+/// Unreadable files, files over `TEXT_DIFF_MAX_BYTES` and files without drift are omitted.
+pub fn schema_diffs(snapshots: &[Snapshot], diffs: &[DiffEntry], row_jump_percent: f64) -> Vec<SchemaDiff> {
+    let roots: HashMap<&str, &Path> = snapshots
+        .iter()
+        .map(|s| (s.date_str.as_str(), s.root.as_path()))
+        .collect();
+
+    diffs
+        .iter()
+        .filter_map(|d| {
+            let (prev, curr) = (d.previous.as_ref()?, d.current.as_ref()?);
+            data_file_delimiter(&curr.rel_path)?;
+            let prev_schema = read_csv_schema(&roots.get(d.prev_date_str.as_str())?.join(&prev.rel_path)).ok()?;
+            let schema = read_csv_schema(&roots.get(d.date_str.as_str())?.join(&curr.rel_path)).ok()?;
+            let drifts = diff_schemas(&prev_schema, &schema, row_jump_percent);
+            if drifts.is_empty() {
+                return None;
+            }
+            Some(SchemaDiff {
+                normalized_rel_path: d.normalized_rel_path.clone(),
+                prev_date_str: d.prev_date_str.clone(),
+                date_str: d.date_str.clone(),
+                prev_schema,
+                schema,
+                drifts,
+            })
+        })
+        .collect()
+}
@@ -0,0 +1,378 @@
+// xlsx.rs - Structural inspection of .xlsx workbooks
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::name::{Namespace, ResolveResult};
+use quick_xml::{NsReader, Reader};
+use serde::Serialize;
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs,
+    io::{self, BufRead, BufReader},
+    path::Path,
+};
+use zip::ZipArchive;
+
+use crate::{DiffEntry, Snapshot};
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SheetInfo {
+    pub name: String,
+    /// `sheetId` from workbook.xml; Excel keeps it when a sheet is renamed
+    pub sheet_id: u32,
+    /// `<dimension ref>` of the sheet (e.g. "A1:D20")
+    pub used_range: Option<String>,
+    /// Number of `<row>` elements in the sheet data
+    pub row_count: usize,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct WorkbookInfo {
+    /// Sheets in workbook order
+    pub sheets: Vec<SheetInfo>,
+    pub defined_names: Vec<String>,
+}
+
+/// Workbook structure of one file in one snapshot.
+#[derive(Debug, Clone)]
+pub struct WorkbookSnapshot {
+    pub normalized_rel_path: String,
+    pub date_str: String,
+    pub info: WorkbookInfo,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WorkbookChangeKind {
+    SheetAdded,
+    SheetRemoved,
+    SheetRenamed,
+    RowsChanged,
+    RangeChanged,
+    NameAdded,
+    NameRemoved,
+}
+
+impl WorkbookChangeKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            WorkbookChangeKind::SheetAdded => "sheet_added",
+            WorkbookChangeKind::SheetRemoved => "sheet_removed",
+            WorkbookChangeKind::SheetRenamed => "sheet_renamed",
+            WorkbookChangeKind::RowsChanged => "rows_changed",
+            WorkbookChangeKind::RangeChanged => "range_changed",
+            WorkbookChangeKind::NameAdded => "name_added",
+            WorkbookChangeKind::NameRemoved => "name_removed",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct WorkbookChange {
+    pub kind: WorkbookChangeKind,
+    /// Sheet name (current name for renames) or defined name
+    pub target: String,
+    pub renamed_from: Option<String>,
+    pub prev_rows: Option<usize>,
+    pub rows: Option<usize>,
+    pub prev_range: Option<String>,
+    pub range: Option<String>,
+}
+
+impl WorkbookChange {
+    pub fn row_delta(&self) -> Option<i64> {
+        Some(self.rows? as i64 - self.prev_rows? as i64)
+    }
+}
+
+/// Structural changes of one workbook between consecutive snapshots.
+#[derive(Debug, Clone)]
+pub struct WorkbookDiff {
+    pub normalized_rel_path: String,
+    pub prev_date_str: String,
+    pub date_str: String,
+    pub changes: Vec<WorkbookChange>,
+}
+
+pub fn is_xlsx(rel_path: &str) -> bool {
+    Path::new(rel_path)
+        .extension()
+        .map(|e| e.eq_ignore_ascii_case("xlsx"))
+        .unwrap_or(false)
+}
+
+fn invalid<E: std::fmt::Display>(e: E) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e.to_string())
+}
+
+fn attr(e: &BytesStart, key: &[u8]) -> Option<String> {
+    e.attributes()
+        .flatten()
+        .find(|a| a.key.as_ref() == key)
+        .and_then(|a| a.unescape_value().ok())
+        .map(|v| v.into_owned())
+}
+
+/// Namespaces of the `r:id` attribute linking a sheet to its part (transitional and strict OOXML).
+const RELATIONSHIP_NAMESPACES: &[&[u8]] = &[
+    b"http://schemas.openxmlformats.org/officeDocument/2006/relationships",
+    b"http://purl.oclc.org/ooxml/officeDocument/relationships",
+];
+
+/// Relationship id of the element just read, whatever prefix the document binds to the
+/// relationships namespace.
+fn relationship_id<R>(reader: &NsReader<R>, e: &BytesStart) -> Option<String> {
+    e.attributes()
+        .flatten()
+        .find(|a| match reader.resolve_attribute(a.key) {
+            (ResolveResult::Bound(Namespace(ns)), local) => {
+                local.as_ref() == b"id" && RELATIONSHIP_NAMESPACES.contains(&ns)
+            }
+            _ => false,
+        })
+        .and_then(|a| a.unescape_value().ok())
+        .map(|v| v.into_owned())
+}
+
+/// Package part path of a relationship target of `xl/workbook.xml`: absolute targets
+/// start at the package root, others at `xl/`, and `.`/`..` segments are resolved.
+fn part_path(target: &str) -> String {
+    let (mut parts, rest) = match target.strip_prefix('/') {
+        Some(abs) => (Vec::new(), abs),
+        None => (vec!["xl"], target),
+    };
+    for segment in rest.split('/') {
+        match segment {
+            "" | "." => {}
+            ".." => {
+                parts.pop();
+            }
+            _ => parts.push(segment),
+        }
+    }
+    parts.join("/")
+}
+
+/// Read the package (zip) parts needed to describe the workbook structure.
+/// Parts are parsed as they are decompressed, so a large sheet is never held in memory.
+pub fn inspect_xlsx(path: &Path) -> io::Result<WorkbookInfo> {
+    let file = fs::File::open(path)?;
+    let mut archive = ZipArchive::new(BufReader::new(file)).map_err(invalid)?;
+
+    // relationship id -> part path inside the package
+    let rels = archive.by_name("xl/_rels/workbook.xml.rels").map_err(invalid)?;
+    let mut targets: HashMap<String, String> = HashMap::new();
+    let mut reader = Reader::from_reader(BufReader::new(rels));
+    let mut buf = Vec::new();
+    loop {
+        match reader.read_event_into(&mut buf).map_err(invalid)? {
+            Event::Start(e) | Event::Empty(e) if e.local_name().as_ref() == b"Relationship" => {
+                if let (Some(id), Some(target)) = (attr(&e, b"Id"), attr(&e, b"Target")) {
+                    targets.insert(id, part_path(&target));
+                }
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+        buf.clear();
+    }
+    // Release the archive for the next part
+    drop(reader);
+
+    // (name, sheetId, part path) in workbook order, plus defined names
+    let workbook = archive.by_name("xl/workbook.xml").map_err(invalid)?;
+    let mut sheet_refs: Vec<(String, u32, Option<String>)> = Vec::new();
+    let mut defined_names = Vec::new();
+    let mut reader = NsReader::from_reader(BufReader::new(workbook));
+    loop {
+        match reader.read_event_into(&mut buf).map_err(invalid)? {
+            Event::Start(e) | Event::Empty(e) => match e.local_name().as_ref() {
+                b"sheet" => {
+                    let name = attr(&e, b"name").unwrap_or_default();
+                    let sheet_id = attr(&e, b"sheetId").and_then(|v| v.parse().ok()).unwrap_or(0);
+                    let part = relationship_id(&reader, &e).and_then(|id| targets.get(&id).cloned());
+                    sheet_refs.push((name, sheet_id, part));
+                }
+                b"definedName" => {
+                    if let Some(name) = attr(&e, b"name") {
+                        defined_names.push(name);
+                    }
+                }
+                _ => {}
+            },
+            Event::Eof => break,
+            _ => {}
+        }
+        buf.clear();
+    }
+    drop(reader);
+
+    let mut sheets = Vec::new();
+    for (name, sheet_id, part) in sheet_refs {
+        let (used_range, row_count) = match part {
+            Some(part) => scan_sheet(BufReader::new(archive.by_name(&part).map_err(invalid)?))?,
+            // Chart sheets and broken references have no sheet data
+            None => (None, 0),
+        };
+        sheets.push(SheetInfo {
+            name,
+            sheet_id,
+            used_range,
+            row_count,
+        });
+    }
+
+    Ok(WorkbookInfo {
+        sheets,
+        defined_names,
+    })
+}
+
+fn scan_sheet(xml: impl BufRead) -> io::Result<(Option<String>, usize)> {
+    let mut reader = Reader::from_reader(xml);
+    let mut buf = Vec::new();
+    let mut used_range = None;
+    let mut rows = 0;
+    loop {
+        match reader.read_event_into(&mut buf).map_err(invalid)? {
+            Event::Start(e) | Event::Empty(e) => match e.local_name().as_ref() {
+                b"dimension" => used_range = attr(&e, b"ref"),
+                b"row" => rows += 1,
+                _ => {}
+            },
+            Event::Eof => break,
+            _ => {}
+        }
+        buf.clear();
+    }
+    Ok((used_range, rows))
+}
+
+/// Compare two workbook structures. Sheets are matched by name; unmatched sheets sharing
+/// a `sheetId` are reported as renamed.
+pub fn diff_workbooks(prev: &WorkbookInfo, curr: &WorkbookInfo) -> Vec<WorkbookChange> {
+    let mut changes = Vec::new();
+    let prev_by_name: BTreeMap<&str, &SheetInfo> =
+        prev.sheets.iter().map(|s| (s.name.as_str(), s)).collect();
+    let curr_names: Vec<&str> = curr.sheets.iter().map(|s| s.name.as_str()).collect();
+
+    let mut unmatched_prev: Vec<&SheetInfo> = prev
+        .sheets
+        .iter()
+        .filter(|s| !curr_names.contains(&s.name.as_str()))
+        .collect();
+
+    for sheet in &curr.sheets {
+        let (old, renamed_from) = match prev_by_name.get(sheet.name.as_str()) {
+            Some(old) => (Some(*old), None),
+            None => match unmatched_prev.iter().position(|p| p.sheet_id == sheet.sheet_id) {
+                Some(i) => {
+                    let old = unmatched_prev.remove(i);
+                    (Some(old), Some(old.name.clone()))
+                }
+                None => (None, None),
+            },
+        };
+
+        let change = |kind| WorkbookChange {
+            kind,
+            target: sheet.name.clone(),
+            renamed_from: renamed_from.clone(),
+            prev_rows: old.map(|o| o.row_count),
+            rows: Some(sheet.row_count),
+            prev_range: old.and_then(|o| o.used_range.clone()),
+            range: sheet.used_range.clone(),
+        };
+
+        match old {
+            None => changes.push(change(WorkbookChangeKind::SheetAdded)),
+            Some(old) => {
+                if renamed_from.is_some() {
+                    changes.push(change(WorkbookChangeKind::SheetRenamed));
+                }
+                if old.row_count != sheet.row_count {
+                    changes.push(change(WorkbookChangeKind::RowsChanged));
+                } else if old.used_range != sheet.used_range {
+                    changes.push(change(WorkbookChangeKind::RangeChanged));
+                }
+            }
+        }
+    }
+
+    for old in unmatched_prev {
+        changes.push(WorkbookChange {
+            kind: WorkbookChangeKind::SheetRemoved,
+            target: old.name.clone(),
+            renamed_from: None,
+            prev_rows: Some(old.row_count),
+            rows: None,
+            prev_range: old.used_range.clone(),
+            range: None,
+        });
+    }
+
+    let name_change = |kind, name: &String| WorkbookChange {
+        kind,
+        target: name.clone(),
+        renamed_from: None,
+        prev_rows: None,
+        rows: None,
+        prev_range: None,
+        range: None,
+    };
+    for name in curr.defined_names.iter().filter(|n| !prev.defined_names.contains(n)) {
+        changes.push(name_change(WorkbookChangeKind::NameAdded, name));
+    }
+    for name in prev.defined_names.iter().filter(|n| !curr.defined_names.contains(n)) {
+        changes.push(name_change(WorkbookChangeKind::NameRemoved, name));
+    }
+
+    changes
+}
+
+/// Inspect every .xlsx file in the snapshots. Files that are not valid packages are skipped.
+pub fn inspect_workbooks(snapshots: &[Snapshot]) -> Vec<WorkbookSnapshot> {
+    snapshots
+        .iter()
+        .flat_map(|snap| {
+            snap.files
+                .iter()
+                .filter(|f| is_xlsx(&f.rel_path))
+                .filter_map(move |f| {
+                    let info = inspect_xlsx(&snap.root.join(&f.rel_path)).ok()?;
+                    Some(WorkbookSnapshot {
+                        normalized_rel_path: f.normalized_rel_path.clone(),
+                        date_str: snap.date_str.clone(),
+                        info,
+                    })
+                })
+        })
+        .collect()
+}
+
+/// Structural diffs for workbooks present on both sides of a diff entry (renames included).
+/// Entries without structural changes are omitted.
+pub fn workbook_diffs(workbooks: &[WorkbookSnapshot], diffs: &[DiffEntry]) -> Vec<WorkbookDiff> {
+    let by_key: HashMap<(&str, &str), &WorkbookInfo> = workbooks
+        .iter()
+        .map(|w| ((w.normalized_rel_path.as_str(), w.date_str.as_str()), &w.info))
+        .collect();
+
+    diffs
+        .iter()
+        .filter(|d| d.previous.is_some() && d.current.is_some())
+        .filter_map(|d| {
+            let prev_path = d.renamed_from.as_deref().unwrap_or(&d.normalized_rel_path);
+            let prev = by_key.get(&(prev_path, d.prev_date_str.as_str()))?;
+            let curr = by_key.get(&(d.normalized_rel_path.as_str(), d.date_str.as_str()))?;
+            let changes = diff_workbooks(prev, curr);
+            if changes.is_empty() {
+                return None;
+            }
+            Some(WorkbookDiff {
+                normalized_rel_path: d.normalized_rel_path.clone(),
+                prev_date_str: d.prev_date_str.clone(),
+                date_str: d.date_str.clone(),
+                changes,
+            })
+        })
+        .collect()
+}
@@ -3,7 +3,7 @@ use std::io::Write;
 use encoding_rs::{SHIFT_JIS, UTF_16LE};
 use encoding_rs_rw::EncodingWriter;
 use chrono::NaiveDate;
-use monthly_file_diff::{FileInfo, collect_files};
+use monthly_file_diff::{FileInfo, Utf16LeWriter, collect_files};
 mod test_fixtures;
 use test_fixtures::TestDataFixture;
 
@@ -72,14 +72,14 @@ fn test_utf16le_encoding() {
     
     let mut buffer = Vec::new();
     {
-        let mut encoder_writer = EncodingWriter::new(&mut buffer, UTF_16LE.new_encoder());
+        let mut encoder_writer = Utf16LeWriter::new(&mut buffer);
         encoder_writer.write_all(test_data.as_bytes()).unwrap();
         encoder_writer.flush().unwrap();
     }
     
     // UTF-16LE should produce different bytes than UTF-8
     assert!(!buffer.is_empty());
-    assert_ne!(buffer.len(), test_data.as_bytes().len());
+    assert_ne!(buffer.len(), test_data.len());
     
     // Decode back to verify
     let (decoded, _, had_errors) = UTF_16LE.decode(&buffer);
@@ -98,6 +98,9 @@ fn test_csv_special_characters() {
         date_str: "2024-08".to_string(),
         rel_path: "sub/file,with,commas.txt".to_string(),
         normalized_rel_path: "sub/file,with,commas.txt".to_string(),
+        content_hash: None,
+        date_shape: None,
+        date_offset: None,
     };
     
     let mut csv_output = Vec::new();
@@ -144,6 +147,9 @@ fn test_multiple_files_csv_format() {
             date_str: "2024-08".to_string(),
             rel_path: "file1.txt".to_string(),
             normalized_rel_path: "file{mm}.txt".to_string(),
+            content_hash: None,
+            date_shape: None,
+            date_offset: None,
         },
         FileInfo {
             actual_name: "file2.txt".to_string(),
@@ -153,6 +159,9 @@ fn test_multiple_files_csv_format() {
             date_str: "2024-12".to_string(),
             rel_path: "file2.txt".to_string(),
             normalized_rel_path: "file{mm}.txt".to_string(),
+            content_hash: None,
+            date_shape: None,
+            date_offset: None,
         },
     ];
     
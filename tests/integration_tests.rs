@@ -4,8 +4,14 @@ use chrono::NaiveDate;
 use tempfile::TempDir;
 
 use monthly_file_diff::{
-    collect_files, extract_dates_from_template, resolve_template
+    collect_files, collect_files_with, extract_dates_from_template, extract_dates_from_template_with,
+    hash_file, parse_normalization_rules, trace_normalization, trace_normalization_with, DateNormalizer, resolve_template, resolve_path, discover_dates, MatchPolicy, CollectOptions, DiscoverOptions, FolderIssueKind,
+    FileInfo, Granularity, HashAlgorithm, diff_consecutive, text_diffs,
+    inspect_xlsx, inspect_workbooks, workbook_diffs, WorkbookChangeKind,
+    read_csv_schema, schema_diffs, SchemaDriftKind, TEXT_DIFF_MAX_BYTES
 };
+mod test_fixtures;
+use test_fixtures::collect_snapshots;
 
 fn create_test_file_structure(base_dir: &Path) -> std::io::Result<()> {
     // Create directory structure: 参照2024_08月データ/Main/
@@ -43,6 +49,59 @@ fn create_test_file_structure(base_dir: &Path) -> std::io::Result<()> {
     Ok(())
 }
 
+/// Write a minimal .xlsx package: sheets as (name, sheetId, row count), plus defined names.
+fn create_xlsx(path: &Path, sheets: &[(&str, u32, usize)], defined_names: &[&str]) {
+    use std::io::Write;
+    use zip::write::SimpleFileOptions;
+
+    let mut zip = zip::ZipWriter::new(fs::File::create(path).unwrap());
+    let opts = SimpleFileOptions::default();
+
+    let mut rels = String::from(r#"<?xml version="1.0" encoding="UTF-8"?><Relationships xmlns="http://schemas.openxmlformats.org/package/2006/relationships">"#);
+    let mut workbook = String::from(r#"<?xml version="1.0" encoding="UTF-8"?><workbook xmlns="http://schemas.openxmlformats.org/spreadsheetml/2006/main" xmlns:r="http://schemas.openxmlformats.org/officeDocument/2006/relationships"><sheets>"#);
+    for (i, (name, sheet_id, rows)) in sheets.iter().enumerate() {
+        let n = i + 1;
+        rels.push_str(&format!(r#"<Relationship Id="rId{n}" Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/worksheet" Target="worksheets/sheet{n}.xml"/>"#));
+        workbook.push_str(&format!(r#"<sheet name="{name}" sheetId="{sheet_id}" r:id="rId{n}"/>"#));
+
+        let mut sheet = format!(r#"<?xml version="1.0" encoding="UTF-8"?><worksheet xmlns="http://schemas.openxmlformats.org/spreadsheetml/2006/main"><dimension ref="A1:C{rows}"/><sheetData>"#);
+        for r in 1..=*rows {
+            sheet.push_str(&format!(r#"<row r="{r}"><c r="A{r}"><v>{r}</v></c></row>"#));
+        }
+        sheet.push_str("</sheetData></worksheet>");
+        zip.start_file(format!("xl/worksheets/sheet{n}.xml"), opts).unwrap();
+        zip.write_all(sheet.as_bytes()).unwrap();
+    }
+    rels.push_str("</Relationships>");
+    workbook.push_str("</sheets><definedNames>");
+    for name in defined_names {
+        workbook.push_str(&format!(r#"<definedName name="{name}">Sheet1!$A$1</definedName>"#));
+    }
+    workbook.push_str("</definedNames></workbook>");
+
+    zip.start_file("xl/_rels/workbook.xml.rels", opts).unwrap();
+    zip.write_all(rels.as_bytes()).unwrap();
+    zip.start_file("xl/workbook.xml", opts).unwrap();
+    zip.write_all(workbook.as_bytes()).unwrap();
+    zip.finish().unwrap();
+}
+
+/// Write one file per month folder (`<yyyy>_<mm>/<rel_path>`) and collect each month
+/// with the same options, returning the file of every month.
+fn collect_month_series(base: &Path, months: &[(i32, u32, &str)], opts: &CollectOptions) -> Vec<FileInfo> {
+    months
+        .iter()
+        .map(|&(y, m, rel_path)| {
+            let root = base.join(format!("{}_{:02}", y, m));
+            let file = root.join(rel_path);
+            fs::create_dir_all(file.parent().unwrap()).unwrap();
+            fs::write(&file, b"x").unwrap();
+            let files = collect_files_with(&root, NaiveDate::from_ymd_opt(y, m, 1).unwrap(), opts);
+            files[0].clone()
+        })
+        .collect()
+}
+
 #[test]
 fn test_collect_files_integration() {
     let temp_dir = TempDir::new().unwrap();
@@ -63,6 +122,7 @@ fn test_collect_files_integration() {
     let xlsx_file = files.iter().find(|f| f.actual_name.contains("InTheBox")).unwrap();
     assert_eq!(xlsx_file.normalized_rel_path, "InTheBox{mm}-{yyyy}.xlsx");
     assert_eq!(xlsx_file.date_str, "2024-08");
+    assert_eq!(xlsx_file.date_shape.as_deref(), Some("{mm}-{yyyy}"));
     
     let pdf_file = files.iter().find(|f| f.actual_name.contains("Report")).unwrap();
     assert_eq!(pdf_file.normalized_rel_path, "Sub/Report{mm}-{yyyy}.pdf");
@@ -85,6 +145,7 @@ fn test_collect_files_without_filename_detection() {
     let info = &files[0];
     assert_eq!(info.rel_path, "report_01_summary.xlsx");
     assert_eq!(info.normalized_rel_path, "report_01_summary.xlsx");
+    assert_eq!(info.date_shape, None);
 }
 
 #[test]
@@ -163,6 +224,323 @@ fn test_extract_dates_invalid_format() {
     assert_eq!(dates.len(), 0);
 }
 
+#[test]
+fn test_discover_dates_strict_and_issues() {
+    let temp_dir = TempDir::new().unwrap();
+    let base_path = temp_dir.path();
+    for dir in ["参照2024_12月データ", "参照2024_12月データ_old", "参照2025_01月データ", "参照2025_13月データ", "backup"] {
+        fs::create_dir_all(base_path.join(dir)).unwrap();
+    }
+    let template = format!("{}/参照{{yyyy}}_{{mm}}月データ/Main", base_path.display());
+    let issue_kinds = |d: &monthly_file_diff::Discovery| -> Vec<(FolderIssueKind, String)> {
+        d.issues
+            .iter()
+            .map(|i| (i.kind, i.path.file_name().unwrap().to_string_lossy().to_string()))
+            .collect()
+    };
+
+    // Unanchored: the stray copy matches too and makes December ambiguous
+    let loose = discover_dates(&template, &DiscoverOptions::default());
+    assert_eq!(loose.dates, vec![
+        NaiveDate::from_ymd_opt(2024, 12, 1).unwrap(),
+        NaiveDate::from_ymd_opt(2025, 1, 1).unwrap(),
+    ]);
+    assert_eq!(issue_kinds(&loose), vec![
+        (FolderIssueKind::NoMatch, "backup".to_string()),
+        (FolderIssueKind::Ambiguous, "参照2024_12月データ".to_string()),
+        (FolderIssueKind::Ambiguous, "参照2024_12月データ_old".to_string()),
+        (FolderIssueKind::InvalidDate, "参照2025_13月データ".to_string()),
+    ]);
+
+    // Strict: the stray copy no longer matches at all
+    let strict = discover_dates(&template, &DiscoverOptions {
+        strict: true,
+        ..DiscoverOptions::default()
+    });
+    assert_eq!(strict.dates, loose.dates);
+    assert_eq!(issue_kinds(&strict), vec![
+        (FolderIssueKind::NoMatch, "backup".to_string()),
+        (FolderIssueKind::NoMatch, "参照2024_12月データ_old".to_string()),
+        (FolderIssueKind::InvalidDate, "参照2025_13月データ".to_string()),
+    ]);
+}
+
+#[test]
+fn test_wildcard_templates() {
+    let temp_dir = TempDir::new().unwrap();
+    let base_path = temp_dir.path();
+    fs::create_dir_all(base_path.join("参照2024_12月データ/Main")).unwrap();
+    fs::create_dir_all(base_path.join("参照2025_01月データ/Main")).unwrap();
+    fs::create_dir_all(base_path.join("参照2025_01月データ(確定版)/Main")).unwrap();
+    let template = format!("{}/参照{{yyyy}}_{{mm}}月データ*/Main", base_path.display());
+
+    let discovery = discover_dates(&template, &DiscoverOptions { strict: true, ..DiscoverOptions::default() });
+    assert_eq!(discovery.dates, vec![
+        NaiveDate::from_ymd_opt(2024, 12, 1).unwrap(),
+        NaiveDate::from_ymd_opt(2025, 1, 1).unwrap(),
+    ]);
+    // The default policy chooses among the January folders, so nothing is ambiguous
+    assert!(discovery.issues.is_empty());
+    let refusing = discover_dates(&template, &DiscoverOptions {
+        strict: true,
+        match_policy: MatchPolicy::Error,
+        ..DiscoverOptions::default()
+    });
+    assert_eq!(refusing.dates, discovery.dates);
+    assert_eq!(refusing.issues.len(), 2);
+    assert!(refusing.issues.iter().all(|i| i.kind == FolderIssueKind::Ambiguous));
+
+    let dec = NaiveDate::from_ymd_opt(2024, 12, 1).unwrap();
+    let jan = NaiveDate::from_ymd_opt(2025, 1, 1).unwrap();
+    assert_eq!(
+        resolve_path(&template, dec, 4, MatchPolicy::Error).unwrap(),
+        base_path.join("参照2024_12月データ/Main")
+    );
+    assert_eq!(
+        resolve_path(&template, jan, 4, MatchPolicy::LexicalLast).unwrap(),
+        base_path.join("参照2025_01月データ(確定版)/Main")
+    );
+    assert!(resolve_path(&template, jan, 4, MatchPolicy::Error).is_err());
+
+    // Make the lexically first January folder the newest, so the two policies disagree
+    #[cfg(unix)]
+    {
+        let set_mtime = |dir: &str, secs: u64| {
+            let mtime = std::time::UNIX_EPOCH + std::time::Duration::from_secs(secs);
+            fs::File::open(base_path.join(dir)).unwrap().set_modified(mtime).unwrap();
+        };
+        set_mtime("参照2025_01月データ", 1_740_000_000);
+        set_mtime("参照2025_01月データ(確定版)", 1_730_000_000);
+        assert_eq!(
+            resolve_path(&template, jan, 4, MatchPolicy::LatestMtime).unwrap(),
+            base_path.join("参照2025_01月データ/Main")
+        );
+    }
+
+    // `?` matches one character; no match leaves the path as written
+    let template = format!("{}/参照{{yyyy}}_{{mm}}月デー?/Main", base_path.display());
+    assert_eq!(resolve_path(&template, dec, 4, MatchPolicy::Error).unwrap(), base_path.join("参照2024_12月データ/Main"));
+    let feb = NaiveDate::from_ymd_opt(2025, 2, 1).unwrap();
+    assert!(!resolve_path(&template, feb, 4, MatchPolicy::Error).unwrap().exists());
+}
+
+#[cfg(not(windows))]
+#[test]
+fn test_backslash_templates_on_unix() {
+    let temp_dir = TempDir::new().unwrap();
+    let base_path = temp_dir.path();
+    fs::create_dir_all(base_path.join("data/参照2024_12月データ/Main")).unwrap();
+
+    let template = format!("{}\\data\\参照{{yyyy}}_{{mm}}月データ\\Main", base_path.display());
+    let dates = extract_dates_from_template(&template);
+    assert_eq!(dates, vec![NaiveDate::from_ymd_opt(2024, 12, 1).unwrap()]);
+    assert!(resolve_template(&template, dates[0]).exists());
+}
+
+#[test]
+fn test_extract_dates_multiple_segments() {
+    let temp_dir = TempDir::new().unwrap();
+    let base_path = temp_dir.path();
+    fs::create_dir_all(base_path.join("2024/11/Main")).unwrap();
+    fs::create_dir_all(base_path.join("2024/12/Main")).unwrap();
+    fs::create_dir_all(base_path.join("2025/01/Main")).unwrap();
+    fs::create_dir_all(base_path.join("notes/01")).unwrap();
+    fs::write(base_path.join("2025/02"), "a file, not a month folder").unwrap();
+
+    let template = format!("{}/{{yyyy}}/{{mm}}/Main", base_path.display());
+    let dates = extract_dates_from_template(&template);
+    assert_eq!(dates, vec![
+        NaiveDate::from_ymd_opt(2024, 11, 1).unwrap(),
+        NaiveDate::from_ymd_opt(2024, 12, 1).unwrap(),
+        NaiveDate::from_ymd_opt(2025, 1, 1).unwrap(),
+    ]);
+}
+
+#[test]
+fn test_extract_dates_repeated_placeholders_must_agree() {
+    let temp_dir = TempDir::new().unwrap();
+    let base_path = temp_dir.path();
+    fs::create_dir_all(base_path.join("2024/参照2024_12月データ")).unwrap();
+    // Misfiled: the year folder disagrees with the year in the folder name
+    fs::create_dir_all(base_path.join("2024/参照2025_01月データ")).unwrap();
+    fs::create_dir_all(base_path.join("2025/参照2025_01月データ")).unwrap();
+
+    let template = format!("{}/{{yyyy}}/参照{{yyyy}}_{{mm}}月データ", base_path.display());
+    let dates = extract_dates_from_template(&template);
+    assert_eq!(dates, vec![
+        NaiveDate::from_ymd_opt(2024, 12, 1).unwrap(),
+        NaiveDate::from_ymd_opt(2025, 1, 1).unwrap(),
+    ]);
+}
+
+#[test]
+fn test_extract_dates_extended_tokens() {
+    let temp_dir = TempDir::new().unwrap();
+    let base_path = temp_dir.path();
+    for dir in ["24/Dec2024", "25/Jan2025", "25/Feb2024", "2025_3月_弥生", "2025_4月_弥生"] {
+        fs::create_dir_all(base_path.join(dir)).unwrap();
+    }
+
+    // {yy} and {yyyy} must agree
+    let template = format!("{}/{{yy}}/{{mmm}}{{yyyy}}", base_path.display());
+    assert_eq!(extract_dates_from_template(&template), vec![
+        NaiveDate::from_ymd_opt(2024, 12, 1).unwrap(),
+        NaiveDate::from_ymd_opt(2025, 1, 1).unwrap(),
+    ]);
+
+    // {m} and {mon_ja} must agree
+    let template = format!("{}/{{yyyy}}_{{m}}月_{{mon_ja}}", base_path.display());
+    assert_eq!(extract_dates_from_template(&template), vec![
+        NaiveDate::from_ymd_opt(2025, 3, 1).unwrap(),
+    ]);
+}
+
+#[test]
+fn test_extract_dates_wareki() {
+    let temp_dir = TempDir::new().unwrap();
+    let base_path = temp_dir.path();
+    for dir in ["平成31年4月", "令和元年5月", "令和6年12月", "R07.01"] {
+        fs::create_dir_all(base_path.join(dir)).unwrap();
+    }
+
+    let template = format!("{}/{{gengo}}{{ey}}年{{m}}月", base_path.display());
+    assert_eq!(extract_dates_from_template(&template), vec![
+        NaiveDate::from_ymd_opt(2019, 4, 1).unwrap(),
+        NaiveDate::from_ymd_opt(2019, 5, 1).unwrap(),
+        NaiveDate::from_ymd_opt(2024, 12, 1).unwrap(),
+    ]);
+
+    let template = format!("{}/{{g}}{{eyy}}.{{mm}}", base_path.display());
+    assert_eq!(extract_dates_from_template(&template), vec![
+        NaiveDate::from_ymd_opt(2025, 1, 1).unwrap(),
+    ]);
+}
+
+#[test]
+fn test_resolve_and_collect_wareki_first_years() {
+    let temp_dir = TempDir::new().unwrap();
+    let base_path = temp_dir.path();
+    // 平成 began on 1989-01-08 and 令和 on 2019-05-01; both spellings of a first year appear
+    let dirs = ["平成元年1月", "平成元年2月", "平成31年5月", "令和元年6月", "令和1年7月"];
+    for dir in dirs {
+        fs::create_dir_all(base_path.join(dir)).unwrap();
+        fs::write(base_path.join(dir).join("data.csv"), "x").unwrap();
+    }
+
+    let template = format!("{}/{{gengo}}{{ey}}年{{m}}月", base_path.display());
+    let dates = extract_dates_from_template(&template);
+    assert_eq!(dates, vec![
+        NaiveDate::from_ymd_opt(1989, 1, 1).unwrap(),
+        NaiveDate::from_ymd_opt(1989, 2, 1).unwrap(),
+        NaiveDate::from_ymd_opt(2019, 5, 1).unwrap(),
+        NaiveDate::from_ymd_opt(2019, 6, 1).unwrap(),
+        NaiveDate::from_ymd_opt(2019, 7, 1).unwrap(),
+    ]);
+    for (date, dir) in dates.iter().zip(dirs) {
+        let path = resolve_path(&template, *date, 4, MatchPolicy::Error).unwrap();
+        assert_eq!(path, base_path.join(dir));
+        let files = collect_files(&path, *date, 10, true);
+        assert_eq!(files.len(), 1, "{}", dir);
+    }
+}
+
+#[test]
+fn test_extract_dates_fiscal_quarters() {
+    let temp_dir = TempDir::new().unwrap();
+    let base_path = temp_dir.path();
+    for dir in ["FY2024/Q1", "FY2024/Q4", "FY2025/Q1", "FY2025/notes"] {
+        fs::create_dir_all(base_path.join(dir)).unwrap();
+    }
+
+    let template = format!("{}/FY{{fy}}/Q{{q}}", base_path.display());
+    let dates = extract_dates_from_template(&template);
+    assert_eq!(dates, vec![
+        NaiveDate::from_ymd_opt(2024, 4, 1).unwrap(),
+        NaiveDate::from_ymd_opt(2025, 1, 1).unwrap(),
+        NaiveDate::from_ymd_opt(2025, 4, 1).unwrap(),
+    ]);
+    // Round trip back to the folders
+    assert_eq!(resolve_template(&template, dates[1]), base_path.join("FY2024/Q4"));
+
+    // January fiscal start: Q1 of FY2024 is January 2024
+    let calendar = extract_dates_from_template_with(&template, &DiscoverOptions {
+        fiscal_start_month: 1,
+        ..DiscoverOptions::default()
+    });
+    assert_eq!(calendar[0], NaiveDate::from_ymd_opt(2024, 1, 1).unwrap());
+}
+
+#[test]
+fn test_extract_dates_fiscal_year_with_month() {
+    let temp_dir = TempDir::new().unwrap();
+    let base_path = temp_dir.path();
+    // Without a calendar year the year follows from the fiscal year
+    for dir in ["FY2024/03", "FY2024/04"] {
+        fs::create_dir_all(base_path.join(dir)).unwrap();
+    }
+    let template = format!("{}/FY{{fy}}/{{mm}}", base_path.display());
+    assert_eq!(extract_dates_from_template(&template), vec![
+        NaiveDate::from_ymd_opt(2024, 4, 1).unwrap(),
+        NaiveDate::from_ymd_opt(2025, 3, 1).unwrap(),
+    ]);
+
+    // March 2025 belongs to FY2024, so the FY2025 folder for it is misfiled
+    for dir in ["FY2024/202503", "FY2025/202503", "FY2025/202504"] {
+        fs::create_dir_all(base_path.join(dir)).unwrap();
+    }
+    let template = format!("{}/FY{{fy}}/{{yyyy}}{{mm}}", base_path.display());
+    assert_eq!(extract_dates_from_template(&template), vec![
+        NaiveDate::from_ymd_opt(2025, 3, 1).unwrap(),
+        NaiveDate::from_ymd_opt(2025, 4, 1).unwrap(),
+    ]);
+}
+
+#[test]
+fn test_extract_dates_daily_and_weekly() {
+    let temp_dir = TempDir::new().unwrap();
+    let base_path = temp_dir.path();
+    // 2024-07-01 (Mon) and 2024-07-05 (Fri) share an ISO week
+    for day in ["2024_07_01", "2024_07_05", "2024_07_08"] {
+        fs::create_dir_all(base_path.join(format!("snap_{}", day))).unwrap();
+    }
+    let template = format!("{}/snap_{{yyyy}}_{{mm}}_{{dd}}", base_path.display());
+
+    let daily = extract_dates_from_template_with(&template, &DiscoverOptions {
+        granularity: Granularity::Daily,
+        ..DiscoverOptions::default()
+    });
+    assert_eq!(daily, vec![
+        NaiveDate::from_ymd_opt(2024, 7, 1).unwrap(),
+        NaiveDate::from_ymd_opt(2024, 7, 5).unwrap(),
+        NaiveDate::from_ymd_opt(2024, 7, 8).unwrap(),
+    ]);
+
+    let weekly = extract_dates_from_template_with(&template, &DiscoverOptions {
+        granularity: Granularity::Weekly,
+        ..DiscoverOptions::default()
+    });
+    assert_eq!(weekly, vec![
+        NaiveDate::from_ymd_opt(2024, 7, 5).unwrap(),
+        NaiveDate::from_ymd_opt(2024, 7, 8).unwrap(),
+    ]);
+
+    // Monthly dates every folder the 1st; the three folders collapse into one ambiguous month
+    let monthly = discover_dates(&template, &DiscoverOptions::default());
+    assert_eq!(monthly.dates, vec![NaiveDate::from_ymd_opt(2024, 7, 1).unwrap()]);
+    assert_eq!(monthly.issues.len(), 3);
+    assert!(monthly.issues.iter().all(|i| i.kind == FolderIssueKind::Ambiguous));
+
+    let root = resolve_template(&template, daily[1]);
+    fs::write(root.join("log_20240705.txt"), "x").unwrap();
+    let opts = CollectOptions {
+        granularity: Granularity::Daily,
+        ..CollectOptions::default()
+    };
+    let files = collect_files_with(&root, daily[1], &opts);
+    assert_eq!(files[0].date_str, "2024-07-05");
+    assert_eq!(files[0].normalized_rel_path, "log_{yyyy}{mm}{dd}.txt");
+}
+
 #[test]
 fn test_resolve_template_integration() {
     let template = "/base/参照{yyyy}_{mm}月データ/Main";
@@ -261,3 +639,276 @@ fn test_nonexistent_directory() {
     // Should handle gracefully and return empty vec
     assert_eq!(files.len(), 0);
 }
+
+#[test]
+fn test_collect_files_with_content_hash() {
+    let temp_dir = TempDir::new().unwrap();
+    let test_dir = temp_dir.path().join("hash_test");
+    fs::create_dir_all(&test_dir).unwrap();
+    fs::write(test_dir.join("abc.txt"), b"abc").unwrap();
+
+    let date = NaiveDate::from_ymd_opt(2024, 6, 1).unwrap();
+
+    // Hashing is off by default
+    let plain = collect_files(&test_dir, date, 2, true);
+    assert_eq!(plain[0].content_hash, None);
+    assert_eq!(plain[0].date_shape, None);
+
+    let opts = CollectOptions {
+        hash: Some(HashAlgorithm::Sha256),
+        ..CollectOptions::default()
+    };
+    let files = collect_files_with(&test_dir, date, &opts);
+    assert_eq!(
+        files[0].content_hash.as_deref(),
+        Some("ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad")
+    );
+
+    let xxh3 = hash_file(&test_dir.join("abc.txt"), HashAlgorithm::Xxh3).unwrap();
+    assert_eq!(xxh3.len(), 16);
+}
+
+#[test]
+fn test_text_diffs_between_months() {
+    let temp_dir = TempDir::new().unwrap();
+    let base_path = temp_dir.path();
+    create_test_file_structure(base_path).unwrap();
+
+    // Shift_JIS text in December, UTF-8 in January
+    let dec_dir = base_path.join("参照2024_12月データ").join("Main");
+    let jan_dir = base_path.join("参照2025_01月データ").join("Main");
+    let (sjis, _, _) = encoding_rs::SHIFT_JIS.encode("見出し\n一行目\n");
+    fs::write(dec_dir.join("Sub").join("Notes12-2024.txt"), &sjis).unwrap();
+    fs::write(jan_dir.join("Sub").join("Notes01-2025.txt"), "見出し\n一行目\n二行目\n").unwrap();
+
+    let template = format!("{}/参照{{yyyy}}_{{mm}}月データ/Main", base_path.display());
+    let dates = [NaiveDate::from_ymd_opt(2024, 12, 1).unwrap(), NaiveDate::from_ymd_opt(2025, 1, 1).unwrap()];
+    let snapshots = collect_snapshots(&template, &dates);
+
+    let diffs = diff_consecutive(&snapshots);
+    let text = text_diffs(&snapshots, &diffs);
+
+    // Binary-ish .xlsx and .pdf are skipped; only the notes changed lines
+    assert_eq!(text.len(), 1);
+    let notes = &text[0];
+    assert_eq!(notes.normalized_rel_path, "Sub/Notes{mm}-{yyyy}.txt");
+    assert_eq!((notes.lines_added, notes.lines_removed), (1, 0));
+    assert!(notes.unified.contains("+二行目"));
+    assert!(notes.unified.contains("--- a/2024-12/Sub/Notes12-2024.txt"));
+}
+
+#[test]
+fn test_inspect_xlsx() {
+    let temp_dir = TempDir::new().unwrap();
+    let path = temp_dir.path().join("book.xlsx");
+    create_xlsx(&path, &[("Summary", 1, 3), ("明細", 2, 10)], &["PrintArea"]);
+
+    let info = inspect_xlsx(&path).unwrap();
+    assert_eq!(info.sheets.len(), 2);
+    assert_eq!(info.sheets[0].name, "Summary");
+    assert_eq!(info.sheets[0].row_count, 3);
+    assert_eq!(info.sheets[0].used_range.as_deref(), Some("A1:C3"));
+    assert_eq!(info.sheets[1].name, "明細");
+    assert_eq!(info.sheets[1].sheet_id, 2);
+    assert_eq!(info.sheets[1].row_count, 10);
+    assert_eq!(info.defined_names, vec!["PrintArea".to_string()]);
+
+    // Not a zip package
+    let fake = temp_dir.path().join("fake.xlsx");
+    fs::write(&fake, b"AAAA").unwrap();
+    assert!(inspect_xlsx(&fake).is_err());
+}
+
+#[test]
+fn test_inspect_xlsx_relationship_prefix_and_targets() {
+    use std::io::Write;
+    use zip::write::SimpleFileOptions;
+
+    // Another prefix for the relationships namespace, and absolute and `..` targets
+    let temp_dir = TempDir::new().unwrap();
+    let path = temp_dir.path().join("book.xlsx");
+    let mut zip = zip::ZipWriter::new(fs::File::create(&path).unwrap());
+    let opts = SimpleFileOptions::default();
+    let parts = [
+        ("xl/_rels/workbook.xml.rels", r#"<Relationships xmlns="http://schemas.openxmlformats.org/package/2006/relationships"><Relationship Id="rId1" Target="/xl/worksheets/sheet1.xml"/><Relationship Id="rId2" Target="../xl/./worksheets/sheet2.xml"/></Relationships>"#),
+        ("xl/workbook.xml", r#"<workbook xmlns="http://schemas.openxmlformats.org/spreadsheetml/2006/main" xmlns:rel="http://schemas.openxmlformats.org/officeDocument/2006/relationships"><sheets><sheet name="A" sheetId="1" rel:id="rId1"/><sheet name="B" sheetId="2" rel:id="rId2"/></sheets></workbook>"#),
+        ("xl/worksheets/sheet1.xml", r#"<worksheet><dimension ref="A1:A1"/><sheetData><row r="1"/></sheetData></worksheet>"#),
+        ("xl/worksheets/sheet2.xml", r#"<worksheet><dimension ref="A1:A2"/><sheetData><row r="1"/><row r="2"/></sheetData></worksheet>"#),
+    ];
+    for (name, xml) in parts {
+        zip.start_file(name, opts).unwrap();
+        zip.write_all(xml.as_bytes()).unwrap();
+    }
+    zip.finish().unwrap();
+
+    let info = inspect_xlsx(&path).unwrap();
+    let rows: Vec<usize> = info.sheets.iter().map(|s| s.row_count).collect();
+    assert_eq!(rows, vec![1, 2]);
+    assert_eq!(info.sheets[1].used_range.as_deref(), Some("A1:A2"));
+}
+
+#[test]
+fn test_workbook_diffs_between_months() {
+    let temp_dir = TempDir::new().unwrap();
+    let base_path = temp_dir.path();
+    create_test_file_structure(base_path).unwrap();
+
+    let template = format!("{}/参照{{yyyy}}_{{mm}}月データ/Main", base_path.display());
+    let dec = NaiveDate::from_ymd_opt(2024, 12, 1).unwrap();
+    let jan = NaiveDate::from_ymd_opt(2025, 1, 1).unwrap();
+    create_xlsx(
+        &resolve_template(&template, dec).join("InTheBox12-2024.xlsx"),
+        &[("Data", 1, 5), ("Old", 2, 2)],
+        &["Totals"],
+    );
+    create_xlsx(
+        &resolve_template(&template, jan).join("InTheBox01-2025.xlsx"),
+        &[("Data", 1, 8), ("Renamed", 2, 2), ("Extra", 3, 1)],
+        &[],
+    );
+
+    let snapshots = collect_snapshots(&template, &[dec, jan]);
+
+    let workbooks = inspect_workbooks(&snapshots);
+    assert_eq!(workbooks.len(), 2);
+
+    let diffs = workbook_diffs(&workbooks, &diff_consecutive(&snapshots));
+    assert_eq!(diffs.len(), 1);
+    assert_eq!(diffs[0].normalized_rel_path, "InTheBox{mm}-{yyyy}.xlsx");
+    let kinds: Vec<WorkbookChangeKind> = diffs[0].changes.iter().map(|c| c.kind).collect();
+    assert_eq!(
+        kinds,
+        vec![
+            WorkbookChangeKind::RowsChanged,
+            WorkbookChangeKind::SheetRenamed,
+            WorkbookChangeKind::SheetAdded,
+            WorkbookChangeKind::NameRemoved,
+        ]
+    );
+    assert_eq!(diffs[0].changes[0].row_delta(), Some(3));
+    assert_eq!(diffs[0].changes[1].renamed_from.as_deref(), Some("Old"));
+}
+
+#[test]
+fn test_read_csv_schema_encodings() {
+    let temp_dir = TempDir::new().unwrap();
+    let text = "社員番号,氏名\n1,山田\n2,佐藤\n";
+
+    let sjis_path = temp_dir.path().join("sjis.csv");
+    fs::write(&sjis_path, encoding_rs::SHIFT_JIS.encode(text).0).unwrap();
+
+    // UTF-16LE with BOM
+    let utf16_path = temp_dir.path().join("utf16.csv");
+    let mut utf16 = vec![0xFF, 0xFE];
+    utf16.extend(text.encode_utf16().flat_map(|u| u.to_le_bytes()));
+    fs::write(&utf16_path, utf16).unwrap();
+
+    // UTF-16LE without a BOM, as some exports write it
+    let no_bom_path = temp_dir.path().join("utf16_no_bom.csv");
+    let no_bom: Vec<u8> = text.encode_utf16().flat_map(|u| u.to_le_bytes()).collect();
+    fs::write(&no_bom_path, no_bom).unwrap();
+
+    for path in [&sjis_path, &utf16_path, &no_bom_path] {
+        let schema = read_csv_schema(path).unwrap();
+        assert_eq!(schema.columns, vec!["社員番号", "氏名"]);
+        assert_eq!(schema.row_count, 2);
+    }
+
+    // Same size limit as text diffs
+    let big_path = temp_dir.path().join("big.csv");
+    fs::File::create(&big_path).unwrap().set_len(TEXT_DIFF_MAX_BYTES + 1).unwrap();
+    assert!(read_csv_schema(&big_path).is_err());
+}
+
+#[test]
+fn test_schema_diffs_between_months() {
+    let temp_dir = TempDir::new().unwrap();
+    let base_path = temp_dir.path();
+    create_test_file_structure(base_path).unwrap();
+
+    let template = format!("{}/参照{{yyyy}}_{{mm}}月データ/Main", base_path.display());
+    let dec = NaiveDate::from_ymd_opt(2024, 12, 1).unwrap();
+    let jan = NaiveDate::from_ymd_opt(2025, 1, 1).unwrap();
+    fs::write(resolve_template(&template, dec).join("Feed12-2024.csv"), "id,amount\n1,10\n").unwrap();
+    fs::write(resolve_template(&template, jan).join("Feed01-2025.csv"), "id,amount,currency\n1,10,JPY\n").unwrap();
+
+    let snapshots = collect_snapshots(&template, &[dec, jan]);
+
+    let drifts = schema_diffs(&snapshots, &diff_consecutive(&snapshots), 50.0);
+    assert_eq!(drifts.len(), 1);
+    assert_eq!(drifts[0].normalized_rel_path, "Feed{mm}-{yyyy}.csv");
+    assert_eq!(drifts[0].drifts.len(), 1);
+    assert_eq!(drifts[0].drifts[0].kind, SchemaDriftKind::ColumnAdded);
+    assert_eq!(drifts[0].drifts[0].column.as_deref(), Some("currency"));
+    assert_eq!(drifts[0].row_delta(), 0);
+}
+
+#[test]
+fn test_collect_files_with_normalization_rules() {
+    let temp_dir = TempDir::new().unwrap();
+    let root = temp_dir.path();
+    fs::create_dir_all(root.join("Sub")).unwrap();
+    fs::write(root.join("Sub/Data_v3_20250103T0912.csv"), b"a").unwrap();
+
+    let date = NaiveDate::from_ymd_opt(2025, 1, 1).unwrap();
+    let opts = CollectOptions {
+        rules: parse_normalization_rules("_v\\d+ =>\n(\\{mm\\})\\d{2}T\\d{4} => $1").unwrap(),
+        ..CollectOptions::default()
+    };
+    let files = collect_files_with(root, date, &opts);
+    assert_eq!(files[0].normalized_rel_path, "Sub/Data_{yyyy}{mm}.csv");
+    assert_eq!(files[0].date_shape.as_deref(), Some("{yyyy}{mm}{dd}"));
+
+    let trace = trace_normalization("Sub/Data_v3_20250103T0912.csv", date, &opts);
+    assert_eq!(trace.date_normalized, "Sub/Data_v3_{yyyy}{mm}03T0912.csv");
+    assert_eq!(trace.steps.iter().map(|s| s.rule).collect::<Vec<_>>(), vec![0, 1]);
+    assert_eq!(trace.normalized(), "Sub/Data_{yyyy}{mm}.csv");
+
+    // One compiled normalizer per snapshot date gives the same trace
+    let normalizer = DateNormalizer::new(date, opts.granularity, opts.month_window);
+    let reused = trace_normalization_with("Sub/Data_v3_20250103T0912.csv", &normalizer, &opts);
+    assert_eq!(reused.normalized(), trace.normalized());
+    assert_eq!(reused.date_shape, trace.date_shape);
+
+    // Rules also apply with filename date detection disabled
+    let opts = CollectOptions { detect_filename_dates: false, ..opts };
+    let files = collect_files_with(root, date, &opts);
+    assert_eq!(files[0].normalized_rel_path, "Sub/Data_20250103T0912.csv");
+}
+
+#[test]
+fn test_collect_files_normalizes_selected_directories() {
+    let temp_dir = TempDir::new().unwrap();
+    let opts = CollectOptions {
+        max_depth: 3,
+        dir_depths: vec![2],
+        ..CollectOptions::default()
+    };
+    let series = collect_month_series(
+        temp_dir.path(),
+        &[(2024, 12, "Main/2024-12/summary.csv"), (2025, 1, "Main/2025-01/summary.csv")],
+        &opts,
+    );
+    assert_eq!(series[0].normalized_rel_path, "Main/{yyyy}-{mm}/summary.csv");
+    assert_eq!(series[0].normalized_rel_path, series[1].normalized_rel_path);
+    // No date in the file name: the directory's shape is recorded
+    assert_eq!(series[1].date_shape.as_deref(), Some("{yyyy}-{mm}"));
+}
+
+#[test]
+fn test_collect_files_with_month_window() {
+    let temp_dir = TempDir::new().unwrap();
+    let opts = CollectOptions {
+        month_window: 1,
+        ..CollectOptions::default()
+    };
+    let closing = collect_month_series(
+        temp_dir.path(),
+        &[(2025, 2, "Closing_01-2025.xlsx"), (2025, 3, "Closing_02-2025.xlsx")],
+        &opts,
+    );
+    assert_eq!(closing[0].normalized_rel_path, "Closing_{mm-1}-{yyyy-1m}.xlsx");
+    assert_eq!(closing[0].normalized_rel_path, closing[1].normalized_rel_path);
+    assert_eq!(closing[1].date_offset, Some(-1));
+    assert_eq!(closing[1].date_shape.as_deref(), Some("{mm}-{yyyy}"));
+}
@@ -1,9 +1,13 @@
 // Test fixtures and helper functions for creating test data
 use std::fs;
 use std::path::{Path, PathBuf};
+use chrono::NaiveDate;
+use monthly_file_diff::{collect_files, resolve_template, Snapshot};
 use tempfile::TempDir;
 
 pub struct TestDataFixture {
+    // Held only to keep the directory alive for the fixture's lifetime
+    #[allow(dead_code)]
     pub temp_dir: TempDir,
     pub base_path: PathBuf,
 }
@@ -146,6 +150,27 @@ impl TestDataFixture {
     }
 }
 
+impl Default for TestDataFixture {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Collects one snapshot per date from the folder the template resolves to
+pub fn collect_snapshots(template: &str, dates: &[NaiveDate]) -> Vec<Snapshot> {
+    dates
+        .iter()
+        .map(|&date| {
+            let root = resolve_template(template, date);
+            Snapshot {
+                date_str: date.format("%Y-%m").to_string(),
+                files: collect_files(&root, date, 3, true),
+                root,
+            }
+        })
+        .collect()
+}
+
 #[cfg(test)]
 mod fixture_tests {
     use super::*;
@@ -209,6 +234,19 @@ mod fixture_tests {
         assert_eq!(dates.len(), 0);
     }
     
+    #[test]
+    fn test_fixture_collect_snapshots() {
+        let fixture = TestDataFixture::new();
+        fixture.create_monthly_structure().unwrap();
+
+        let dates = [NaiveDate::from_ymd_opt(2024, 8, 1).unwrap(), NaiveDate::from_ymd_opt(2025, 1, 1).unwrap()];
+        let snapshots = collect_snapshots(&fixture.monthly_template(), &dates);
+        assert_eq!(snapshots.len(), 2);
+        assert_eq!(snapshots[1].date_str, "2025-01");
+        assert_eq!(snapshots[1].root, fixture.path().join("参照2025_01月データ/Main"));
+        assert_eq!(snapshots[1].files.len(), 2);
+    }
+    
     #[test]
     fn test_fixture_special_char_files() {
         let fixture = TestDataFixture::new();
@@ -1,9 +1,33 @@
 use chrono::NaiveDate;
+use std::path::PathBuf;
 use monthly_file_diff::{
     resolve_template, normalize_filename, normalize_rel_path, 
-    datetime_str_to_iso8601_jst, sanitize_id
+    datetime_str_to_iso8601_jst, sanitize_id, sanitize_id_base,
+    FileInfo, Snapshot, ChangeKind, HashAlgorithm, diff_snapshots, diff_consecutive, name_similarity,
+    BaselineStatus, compare_to_baseline, parse_snapshot_date,
+    expand_env_with, map_path_prefix, parse_normalization_rules, apply_normalization_rules, parse_path_mappings, PathMapping, Granularity, Rollup, date_range, last_periods, fiscal_year_quarter, fiscal_quarter_start, roll_up_dates,
+    to_wareki, from_wareki, JAPANESE_ERAS, DateNormalizer,
+    decode_text, is_text_like, text_diff,
+    CsvSchema, SchemaDriftKind, diff_schemas, parse_csv_schema,
+    AnomalyKind, AnomalyThresholds, Severity, detect_size_anomalies,
+    presence_matrix, disambiguate_collisions
 };
 
+fn file_info(norm: &str, date_str: &str, size: u64, modified: &str) -> FileInfo {
+    FileInfo {
+        actual_name: norm.to_string(),
+        size,
+        created: "2024/08/01 09:00".to_string(),
+        modified: modified.to_string(),
+        date_str: date_str.to_string(),
+        rel_path: norm.to_string(),
+        normalized_rel_path: norm.to_string(),
+        content_hash: None,
+        date_shape: None,
+        date_offset: None,
+    }
+}
+
 #[test]
 fn test_resolve_template() {
     let template = "D:/data/参照{yyyy}_{mm}月データ/Main";
@@ -44,6 +68,115 @@ fn test_normalize_filename() {
     assert_eq!(result3, "document.txt");
 }
 
+#[test]
+fn test_resolve_template_extended_tokens() {
+    let date = NaiveDate::from_ymd_opt(2025, 1, 5).unwrap();
+    assert_eq!(
+        resolve_template("/d/{yy}{mm}/{yyyy}_{m}月/{mmm}{yyyy}/{mon_ja}", date),
+        PathBuf::from("/d/2501/2025_1月/Jan2025/睦月")
+    );
+}
+
+#[test]
+fn test_normalize_filename_extended_tokens() {
+    assert_eq!(normalize_filename("売上2025_1月.xlsx", 2025, 1), "売上{yyyy}_{m}月.xlsx");
+    assert_eq!(normalize_filename("Sales_Jan2025.csv", 2025, 1), "Sales_{mmm}{yyyy}.csv");
+    assert_eq!(normalize_filename("sales_JAN_2025.csv", 2025, 1), "sales_{mmm}_{yyyy}.csv");
+    // not a month inside a longer word
+    assert_eq!(normalize_filename("Janitor.txt", 2025, 1), "Janitor.txt");
+    assert_eq!(normalize_filename("報告_睦月.docx", 2025, 1), "報告_{mon_ja}.docx");
+    assert_eq!(normalize_filename("report_2501.xlsx", 2025, 1), "report_{yy}{mm}.xlsx");
+    assert_eq!(normalize_filename("report_01-25.xlsx", 2025, 1), "report_{mm}-{yy}.xlsx");
+    // a lone two-digit year or unpadded month is left alone
+    assert_eq!(normalize_filename("v25_1.txt", 2025, 1), "v25_1.txt");
+    let daily = DateNormalizer::new(NaiveDate::from_ymd_opt(2025, 1, 15).unwrap(), Granularity::Daily, 0);
+    assert_eq!(daily.normalize("log_250115.txt").normalized, "log_{yy}{mm}{dd}.txt");
+}
+
+#[test]
+fn test_wareki_conversion() {
+    let ymd = |y, m, d| NaiveDate::from_ymd_opt(y, m, d).unwrap();
+    let (era, ey) = to_wareki(ymd(2024, 12, 1)).unwrap();
+    assert_eq!((era.name, ey), ("令和", 6));
+    // Era boundaries
+    let (era, ey) = to_wareki(ymd(2019, 4, 30)).unwrap();
+    assert_eq!((era.name, ey), ("平成", 31));
+    let (era, ey) = to_wareki(ymd(2019, 5, 1)).unwrap();
+    assert_eq!((era.name, ey), ("令和", 1));
+    let (era, ey) = to_wareki(ymd(1989, 1, 7)).unwrap();
+    assert_eq!((era.name, ey), ("昭和", 64));
+    assert!(to_wareki(ymd(1900, 1, 1)).is_none());
+
+    assert_eq!(from_wareki(&JAPANESE_ERAS[0], 6), 2024);
+    assert_eq!(from_wareki(&JAPANESE_ERAS[1], 31), 2019);
+
+    assert_eq!(
+        resolve_template("/d/{gengo}{ey}年{mm}月/{g}{eyy}.{mm}", ymd(2024, 12, 1)),
+        PathBuf::from("/d/令和6年12月/R06.12")
+    );
+    // The first year of an era is 元年, as discovery reads it
+    assert_eq!(
+        resolve_template("/d/{gengo}{ey}年{m}月/{g}{eyy}", ymd(2019, 6, 1)),
+        PathBuf::from("/d/令和元年6月/R01")
+    );
+}
+
+#[test]
+fn test_normalize_filename_wareki() {
+    assert_eq!(normalize_filename("集計_令和6年12月.xlsx", 2024, 12), "集計_{gengo}{ey}年{mm}月.xlsx");
+    assert_eq!(normalize_filename("集計_令和元年5月.xlsx", 2019, 5), "集計_{gengo}{ey}年{m}月.xlsx");
+    assert_eq!(normalize_filename("集計_平成31年4月.xlsx", 2019, 4), "集計_{gengo}{ey}年{m}月.xlsx");
+    assert_eq!(normalize_filename("R06.12_売上.csv", 2024, 12), "{g}{eyy}.{mm}_売上.csv");
+    assert_eq!(normalize_filename("売上R6-12.csv", 2024, 12), "売上{g}{ey}-{mm}.csv");
+    // another era's year number is not this date
+    assert_eq!(normalize_filename("H06.12.csv", 2024, 12), "H06.{mm}.csv");
+}
+
+#[test]
+fn test_expand_env() {
+    let lookup = |name: &str| match name {
+        "HOME" => Some("/home/taro".to_string()),
+        "DATA_ROOT" => Some("/mnt/d/data".to_string()),
+        "ProgramFiles(x86)" => Some("C:\\Program Files (x86)".to_string()),
+        _ => None,
+    };
+    assert_eq!(expand_env_with("~/data/{yyyy}", lookup).unwrap(), "/home/taro/data/{yyyy}");
+    assert_eq!(expand_env_with("$DATA_ROOT/参照{yyyy}_{mm}", lookup).unwrap(), "/mnt/d/data/参照{yyyy}_{mm}");
+    assert_eq!(expand_env_with("${DATA_ROOT}_old/{mm}", lookup).unwrap(), "/mnt/d/data_old/{mm}");
+    assert_eq!(expand_env_with("%DATA_ROOT%\\{yyyy}", lookup).unwrap(), "/mnt/d/data\\{yyyy}");
+    assert_eq!(expand_env_with("%ProgramFiles(x86)%\\x", lookup).unwrap(), "C:\\Program Files (x86)\\x");
+    // ~ only at the start, lone $ and % are literal
+    assert_eq!(expand_env_with("a~b/$/100%", lookup).unwrap(), "a~b/$/100%");
+
+    let err = expand_env_with("$NOPE/{yyyy}", lookup).unwrap_err();
+    assert!(err.contains("NOPE"));
+    assert!(expand_env_with("~/x", |_| None).is_err());
+}
+
+#[test]
+fn test_map_path_prefix() {
+    let mappings = parse_path_mappings(
+        "# Windows drives and shares on the Linux workers\n\
+         D:=/mnt/d\n\
+         \\\\fileserver\\share = /mnt/share/\n",
+    )
+    .unwrap();
+    assert_eq!(mappings[0], PathMapping { from: "D:".to_string(), to: "/mnt/d".to_string() });
+
+    assert_eq!(
+        map_path_prefix("D:\\data\\参照{yyyy}_{mm}月データ\\Main", &mappings),
+        "/mnt/d\\data\\参照{yyyy}_{mm}月データ\\Main"
+    );
+    assert_eq!(map_path_prefix("d:/data", &mappings), "/mnt/d/data");
+    assert_eq!(map_path_prefix("\\\\FileServer\\share\\月次", &mappings), "/mnt/share\\月次");
+    // Prefix must end at a separator; unmapped templates are unchanged
+    assert_eq!(map_path_prefix("D:x/data", &mappings), "D:x/data");
+    assert_eq!(map_path_prefix("/srv/data", &mappings), "/srv/data");
+
+    assert!("no-equals-sign".parse::<PathMapping>().is_err());
+    assert!(parse_path_mappings("D:=/mnt/d\nbroken").is_err());
+}
+
 #[test]
 fn test_normalize_filename_edge_cases() {
     // Test year appears multiple times
@@ -57,6 +190,153 @@ fn test_normalize_filename_edge_cases() {
     assert_eq!(result2, "data{mm}file.txt");
 }
 
+#[test]
+fn test_normalize_filename_date_shapes() {
+    // Only the digits forming the folder's date are replaced
+    assert_eq!(normalize_filename("Report_12_items_12-2024.csv", 2024, 12), "Report_12_items_{mm}-{yyyy}.csv");
+    assert_eq!(normalize_filename("SubFile101_01-2025.xlsx", 2025, 1), "SubFile101_{mm}-{yyyy}.xlsx");
+    assert_eq!(normalize_filename("集計2024年12月_v12.xlsx", 2024, 12), "集計{yyyy}年{mm}月_v12.xlsx");
+    // A date of another month is not this folder's date
+    assert_eq!(normalize_filename("Closing_11-2024.xlsx", 2024, 12), "Closing_11-{yyyy}.xlsx");
+    // Monthly snapshots keep the day digits
+    assert_eq!(normalize_filename("export_20241203T0912.csv", 2024, 12), "export_{yyyy}{mm}03T0912.csv");
+
+    let date = NaiveDate::from_ymd_opt(2024, 12, 3).unwrap();
+    let shape = |name: &str, g| DateNormalizer::new(date, g, 0).normalize(name).shape;
+    assert_eq!(shape("Data202412.csv", Granularity::Monthly), Some("{yyyy}{mm}"));
+    assert_eq!(shape("Data2024-12.csv", Granularity::Monthly), Some("{yyyy}-{mm}"));
+    assert_eq!(shape("Data12_2024.csv", Granularity::Monthly), Some("{mm}-{yyyy}"));
+    assert_eq!(shape("集計2024年12月.csv", Granularity::Monthly), Some("{yyyy}年{mm}月"));
+    assert_eq!(shape("log_20241203.txt", Granularity::Daily), Some("{yyyy}{mm}{dd}"));
+    assert_eq!(shape("data12.txt", Granularity::Monthly), Some("{mm}"));
+    assert_eq!(shape("document.txt", Granularity::Monthly), None);
+}
+
+#[test]
+fn test_normalization_rules() {
+    let rules = parse_normalization_rules(
+        "# version suffixes and export timestamps\n\
+         _v\\d+ =>\n\
+         \n\
+         _(\\d{8})T\\d{4} => _${1}\n",
+    )
+    .unwrap();
+    assert_eq!(rules.len(), 2);
+
+    let (path, steps) = apply_normalization_rules("Main/Data_v3_20250103T0912.csv", &rules);
+    assert_eq!(path, "Main/Data_20250103.csv");
+    assert_eq!(steps.len(), 2);
+    assert_eq!((steps[0].rule, steps[0].result.as_str()), (0, "Main/Data_20250103T0912.csv"));
+
+    // Rules that do not change the path are not steps
+    let (path, steps) = apply_normalization_rules("Main/Data_20250103T0912.csv", &rules);
+    assert_eq!(path, "Main/Data_20250103.csv");
+    assert_eq!(steps[0].rule, 1);
+
+    let err = parse_normalization_rules("a => b\n([ => x").unwrap_err();
+    assert!(err.starts_with("line 2:"));
+    assert!(parse_normalization_rules("no arrow").is_err());
+}
+
+#[test]
+fn test_normalize_rel_path_dirs() {
+    let date = NaiveDate::from_ymd_opt(2024, 12, 1).unwrap();
+    let normalizer = DateNormalizer::new(date, Granularity::Monthly, 0);
+    let found = normalizer.normalize_rel_path("Main/2024-12/Detail_12.csv", &[2]);
+    assert_eq!(found.normalized, "Main/{yyyy}-{mm}/Detail_{mm}.csv");
+    assert_eq!(found.shape, Some("{mm}"));
+    // Only the selected depths; without any the directories are kept
+    assert_eq!(
+        normalizer.normalize_rel_path("2024/12/Detail_12.csv", &[2]).normalized,
+        "2024/{mm}/Detail_{mm}.csv"
+    );
+    assert_eq!(
+        normalizer.normalize_rel_path("Main\\2024-12\\Detail_12.csv", &[]).normalized,
+        "Main/2024-12/Detail_{mm}.csv"
+    );
+}
+
+#[test]
+fn test_disambiguate_collisions() {
+    let actual = |rel: &str, date_str: &str, shape: &str| FileInfo {
+        rel_path: rel.to_string(),
+        normalized_rel_path: "Data{yyyy}-{mm}.csv".to_string(),
+        date_shape: Some(shape.to_string()),
+        ..file_info("Data{yyyy}-{mm}.csv", date_str, 10, "2024/12/01 09:00")
+    };
+    let snapshot = |date_str: &str, files: Vec<FileInfo>| Snapshot {
+        date_str: date_str.to_string(),
+        root: PathBuf::from(date_str),
+        files,
+    };
+    let mut snapshots = vec![
+        snapshot(
+            "2024-12",
+            vec![
+                actual("Data2024-12.csv", "2024-12", "{yyyy}-{mm}"),
+                actual("Data12-2024.csv", "2024-12", "{mm}-{yyyy}"),
+                file_info("Other.csv", "2024-12", 10, "2024/12/01 09:00"),
+            ],
+        ),
+        // Only the second file this month: it must stay in its own series
+        snapshot("2025-01", vec![actual("Data01-2025.csv", "2025-01", "{mm}-{yyyy}")]),
+    ];
+    let collisions = disambiguate_collisions(&mut snapshots);
+    assert_eq!(collisions.len(), 1);
+    assert_eq!(collisions[0].date_str, "2024-12");
+    assert_eq!(collisions[0].normalized_rel_path, "Data{yyyy}-{mm}.csv");
+    assert_eq!(collisions[0].rel_paths, vec!["Data12-2024.csv", "Data2024-12.csv"]);
+    assert_eq!(snapshots[0].files[0].normalized_rel_path, "Data{yyyy}-{mm}.csv#{yyyy}-{mm}");
+    assert_eq!(snapshots[0].files[1].normalized_rel_path, "Data{yyyy}-{mm}.csv#{mm}-{yyyy}");
+    assert_eq!(snapshots[0].files[2].normalized_rel_path, "Other.csv");
+    assert_eq!(snapshots[1].files[0].normalized_rel_path, "Data{yyyy}-{mm}.csv#{mm}-{yyyy}");
+
+    assert!(disambiguate_collisions(&mut snapshots).is_empty());
+
+    // Same shape on both files: fall back to the actual path
+    let mut snapshots = vec![snapshot(
+        "2024-12",
+        vec![
+            actual("a/Data2024-12.csv", "2024-12", "{yyyy}-{mm}"),
+            actual("b/Data2024-12.csv", "2024-12", "{yyyy}-{mm}"),
+        ],
+    )];
+    disambiguate_collisions(&mut snapshots);
+    assert_eq!(snapshots[0].files[0].normalized_rel_path, "Data{yyyy}-{mm}.csv#a/Data2024-12.csv");
+    assert_eq!(snapshots[0].files[1].normalized_rel_path, "Data{yyyy}-{mm}.csv#b/Data2024-12.csv");
+}
+
+#[test]
+fn test_date_normalizer_month_window() {
+    let march = NaiveDate::from_ymd_opt(2025, 3, 1).unwrap();
+    let monthly = Granularity::Monthly;
+
+    let found = DateNormalizer::new(march, monthly, 1).normalize("Closing_02-2025.xlsx");
+    assert_eq!(found.normalized, "Closing_{mm-1}-{yyyy-1m}.xlsx");
+    assert_eq!((found.shape, found.offset), (Some("{mm}-{yyyy}"), Some(-1)));
+
+    // The folder's own month is matched first, nearby months in what it left
+    let found = DateNormalizer::new(march, monthly, 1).normalize("Report_03-2025_vs_02-2025.csv");
+    assert_eq!(found.normalized, "Report_{mm}-{yyyy}_vs_{mm-1}-{yyyy-1m}.csv");
+    assert_eq!(found.offset, Some(0));
+    let found = DateNormalizer::new(march, monthly, 0).normalize("Report_03-2025_vs_02-2025.csv");
+    assert_eq!(found.normalized, "Report_{mm}-{yyyy}_vs_02-{yyyy}.csv");
+
+    // Across the year boundary, and two months back with a wider window
+    let january = NaiveDate::from_ymd_opt(2025, 1, 1).unwrap();
+    let found = DateNormalizer::new(january, monthly, 1).normalize("Closing_202412.xlsx");
+    assert_eq!(found.normalized, "Closing_{yyyy-1m}{mm-1}.xlsx");
+    let found = DateNormalizer::new(january, monthly, 2).normalize("Closing_2024年11月.xlsx");
+    assert_eq!((found.normalized.as_str(), found.offset), ("Closing_{yyyy-2m}年{mm-2}月.xlsx", Some(-2)));
+
+    // Outside the window only the year is normalized, which tells no month offset
+    let found = DateNormalizer::new(march, monthly, 1).normalize("Closing_01-2025.xlsx");
+    assert_eq!((found.normalized.as_str(), found.offset), ("Closing_01-{yyyy}.xlsx", None));
+    assert_eq!(found.shape, Some("{yyyy}"));
+    assert_eq!(DateNormalizer::new(march, monthly, 1).normalize("data03.txt").offset, Some(0));
+    assert_eq!(DateNormalizer::new(march, monthly, 3).normalize("notes.txt").offset, None);
+}
+
 #[test]
 fn test_normalize_rel_path() {
     // Test root level file
@@ -99,26 +379,503 @@ fn test_datetime_str_to_iso8601_jst() {
 #[test]
 fn test_sanitize_id() {
     let input = "Sub/InTheBox{mm}-{yyyy}.xlsx";
-    let result = sanitize_id(input);
+    let result = sanitize_id_base(input);
     assert_eq!(result, "Sub_InTheBox_mm___yyyy__xlsx");
     
     // Test alphanumeric only
     let input2 = "file123ABC";
-    let result2 = sanitize_id(input2);
+    let result2 = sanitize_id_base(input2);
     assert_eq!(result2, "file123ABC");
     
     // Test special characters
     let input3 = "test@#$%file.txt";
-    let result3 = sanitize_id(input3);
+    let result3 = sanitize_id_base(input3);
     assert_eq!(result3, "test____file_txt");
 }
 
 #[test]
 fn test_sanitize_id_empty() {
-    let result = sanitize_id("");
+    let result = sanitize_id_base("");
     assert_eq!(result, "");
 }
 
+#[test]
+fn test_sanitize_id_appends_hash() {
+    let a = sanitize_id("Sub/a.txt");
+    let b = sanitize_id("Sub_a.txt");
+    assert!(a.starts_with("Sub_a_txt_"));
+    // Same sanitized base, different inputs -> different ids
+    assert_ne!(a, b);
+}
+
+#[test]
+fn test_diff_snapshots_classification() {
+    let prev = Snapshot {
+        date_str: "2024-12".to_string(),
+        root: PathBuf::new(),
+        files: vec![
+            file_info("same.txt", "2024-12", 10, "2024/12/01 09:00"),
+            file_info("grown.txt", "2024-12", 10, "2024/12/01 09:00"),
+            file_info("touched.txt", "2024-12", 10, "2024/12/01 09:00"),
+            file_info("gone.txt", "2024-12", 10, "2024/12/01 09:00"),
+        ],
+    };
+    let curr = Snapshot {
+        date_str: "2025-01".to_string(),
+        root: PathBuf::new(),
+        files: vec![
+            file_info("same.txt", "2025-01", 10, "2024/12/01 09:00"),
+            file_info("grown.txt", "2025-01", 25, "2025/01/02 10:00"),
+            file_info("touched.txt", "2025-01", 10, "2025/01/02 10:00"),
+            file_info("new.txt", "2025-01", 5, "2025/01/02 10:00"),
+        ],
+    };
+
+    let diffs = diff_snapshots(&prev, &curr);
+    let kind_of = |name: &str| diffs.iter().find(|d| d.normalized_rel_path == name).unwrap().kind;
+
+    assert_eq!(diffs.len(), 5);
+    assert_eq!(kind_of("same.txt"), ChangeKind::Unchanged);
+    assert_eq!(kind_of("grown.txt"), ChangeKind::SizeChanged);
+    assert_eq!(kind_of("touched.txt"), ChangeKind::MtimeChanged);
+    assert_eq!(kind_of("gone.txt"), ChangeKind::Removed);
+    assert_eq!(kind_of("new.txt"), ChangeKind::Added);
+
+    let grown = diffs.iter().find(|d| d.normalized_rel_path == "grown.txt").unwrap();
+    assert_eq!(grown.size_delta(), Some(15));
+    assert_eq!(grown.prev_date_str, "2024-12");
+    assert_eq!(grown.date_str, "2025-01");
+}
+
+#[test]
+fn test_diff_consecutive_pairs_neighbours_only() {
+    let snapshots: Vec<Snapshot> = ["2024-11", "2024-12", "2025-01"]
+        .iter()
+        .map(|d| Snapshot {
+            date_str: d.to_string(),
+            root: PathBuf::new(),
+            files: vec![file_info("a.txt", d, 1, "2024/12/01 09:00")],
+        })
+        .collect();
+
+    let diffs = diff_consecutive(&snapshots);
+    assert_eq!(diffs.len(), 2);
+    assert_eq!(diffs[0].prev_date_str, "2024-11");
+    assert_eq!(diffs[1].prev_date_str, "2024-12");
+    assert!(diffs.iter().all(|d| d.kind == ChangeKind::Unchanged));
+
+    assert!(diff_consecutive(&snapshots[..1]).is_empty());
+}
+
+#[test]
+fn test_diff_snapshots_with_content_hash() {
+    let hashed = |norm: &str, date: &str, modified: &str, hash: &str| FileInfo {
+        content_hash: Some(hash.to_string()),
+        ..file_info(norm, date, 10, modified)
+    };
+    let prev = Snapshot {
+        date_str: "2024-12".to_string(),
+        root: PathBuf::new(),
+        files: vec![
+            hashed("rewritten.txt", "2024-12", "2024/12/01 09:00", "aaaa"),
+            hashed("touched.txt", "2024-12", "2024/12/01 09:00", "bbbb"),
+        ],
+    };
+    let curr = Snapshot {
+        date_str: "2025-01".to_string(),
+        root: PathBuf::new(),
+        files: vec![
+            // Same size and timestamp, different bytes
+            hashed("rewritten.txt", "2025-01", "2024/12/01 09:00", "cccc"),
+            // Touched only
+            hashed("touched.txt", "2025-01", "2025/01/05 12:00", "bbbb"),
+        ],
+    };
+
+    let diffs = diff_snapshots(&prev, &curr);
+    assert_eq!(diffs[0].normalized_rel_path, "rewritten.txt");
+    assert_eq!(diffs[0].kind, ChangeKind::ContentChanged);
+    assert_eq!(diffs[0].content_differs(), Some(true));
+    assert_eq!(diffs[1].kind, ChangeKind::Unchanged);
+    assert_eq!(diffs[1].content_differs(), Some(false));
+}
+
+#[test]
+fn test_diff_snapshots_detects_renames() {
+    let prev = Snapshot {
+        date_str: "2024-12".to_string(),
+        root: PathBuf::new(),
+        files: vec![
+            // Moved to another folder, same content
+            FileInfo {
+                content_hash: Some("h1".to_string()),
+                ..file_info("Sub/SubFile1_{mm}-{yyyy}.txt", "2024-12", 8, "2024/12/01 09:00")
+            },
+            // Renamed, no hashes: paired by size and name similarity
+            file_info("Report_{mm}.pdf", "2024-12", 40, "2024/12/01 09:00"),
+            // Removed for real
+            file_info("obsolete.txt", "2024-12", 3, "2024/12/01 09:00"),
+        ],
+    };
+    let curr = Snapshot {
+        date_str: "2025-01".to_string(),
+        root: PathBuf::new(),
+        files: vec![
+            FileInfo {
+                content_hash: Some("h1".to_string()),
+                ..file_info("Archive/SubFile1_{mm}-{yyyy}.txt", "2025-01", 8, "2025/01/02 10:00")
+            },
+            file_info("Report_{mm}_final.pdf", "2025-01", 40, "2025/01/02 10:00"),
+            file_info("brand_new.txt", "2025-01", 3, "2025/01/02 10:00"),
+        ],
+    };
+
+    let diffs = diff_snapshots(&prev, &curr);
+    let entry = |name: &str| diffs.iter().find(|d| d.normalized_rel_path == name).unwrap();
+
+    assert_eq!(diffs.len(), 4);
+    let moved = entry("Archive/SubFile1_{mm}-{yyyy}.txt");
+    assert_eq!(moved.kind, ChangeKind::Renamed);
+    assert_eq!(moved.renamed_from.as_deref(), Some("Sub/SubFile1_{mm}-{yyyy}.txt"));
+    assert_eq!(moved.previous.as_ref().unwrap().size, 8);
+
+    let renamed = entry("Report_{mm}_final.pdf");
+    assert_eq!(renamed.kind, ChangeKind::Renamed);
+    assert_eq!(renamed.renamed_from.as_deref(), Some("Report_{mm}.pdf"));
+
+    // Same size but dissimilar names stay removed + added
+    assert_eq!(entry("obsolete.txt").kind, ChangeKind::Removed);
+    assert_eq!(entry("brand_new.txt").kind, ChangeKind::Added);
+}
+
+#[test]
+fn test_diff_snapshots_identical_content_needs_similar_names() {
+    let hashed = |norm: &str, date_str: &str, size: u64, hash: &str| FileInfo {
+        content_hash: Some(hash.to_string()),
+        ..file_info(norm, date_str, size, "2024/12/01 09:00")
+    };
+    let prev = Snapshot {
+        date_str: "2024-12".to_string(),
+        root: PathBuf::new(),
+        files: vec![
+            // Two unrelated empty files share the empty-content hash
+            hashed(".gitkeep", "2024-12", 0, "empty"),
+            hashed("lock_a.txt", "2024-12", 0, "empty"),
+            // Identical boilerplate under unrelated names
+            hashed("README_old.txt", "2024-12", 20, "boilerplate"),
+        ],
+    };
+    let curr = Snapshot {
+        date_str: "2025-01".to_string(),
+        root: PathBuf::new(),
+        files: vec![
+            hashed("done.flag", "2025-01", 0, "empty"),
+            hashed("lock_b.txt", "2025-01", 0, "empty"),
+            hashed("LICENSE", "2025-01", 20, "boilerplate"),
+        ],
+    };
+
+    let diffs = diff_snapshots(&prev, &curr);
+    assert_eq!(diffs.len(), 6);
+    assert!(diffs.iter().all(|d| d.kind != ChangeKind::Renamed));
+    assert_eq!(diffs.iter().filter(|d| d.kind == ChangeKind::Removed).count(), 3);
+    assert_eq!(diffs.iter().filter(|d| d.kind == ChangeKind::Added).count(), 3);
+}
+
+#[test]
+fn test_name_similarity() {
+    assert_eq!(name_similarity("abc", "abc"), 1.0);
+    assert_eq!(name_similarity("", ""), 1.0);
+    assert_eq!(name_similarity("abc", "xyz"), 0.0);
+    assert!((name_similarity("kitten", "sitting") - (1.0 - 3.0 / 7.0)).abs() < 1e-9);
+}
+
+#[test]
+fn test_compare_to_baseline() {
+    let snapshot = |date: &str, files: &[(&str, u64)]| Snapshot {
+        date_str: date.to_string(),
+        root: PathBuf::new(),
+        files: files
+            .iter()
+            .map(|(name, size)| file_info(name, date, *size, "2024/12/01 09:00"))
+            .collect(),
+    };
+    let baseline = snapshot("2024-12", &[("a.txt", 200), ("b.txt", 50), ("empty.txt", 0)]);
+    let snapshots = vec![
+        baseline.clone(),
+        snapshot("2025-01", &[("a.txt", 250), ("b.txt", 50), ("empty.txt", 10), ("c.txt", 1)]),
+        snapshot("2025-02", &[("a.txt", 150)]),
+    ];
+
+    let deltas = compare_to_baseline(&baseline, &snapshots);
+    // The baseline month itself is not compared
+    assert_eq!(deltas.len(), 2);
+
+    let jan = &deltas[0];
+    assert_eq!(jan.baseline_date_str, "2024-12");
+    assert_eq!(jan.date_str, "2025-01");
+    assert_eq!(jan.count(BaselineStatus::New), 1);
+    assert_eq!(jan.count(BaselineStatus::SizeDrift), 2);
+    assert_eq!(jan.count(BaselineStatus::Same), 1);
+    let a = jan.entries.iter().find(|e| e.normalized_rel_path == "a.txt").unwrap();
+    assert_eq!(a.delta_bytes(), Some(50));
+    assert_eq!(a.delta_percent(), Some(25.0));
+    let empty = jan.entries.iter().find(|e| e.normalized_rel_path == "empty.txt").unwrap();
+    assert_eq!(empty.delta_percent(), None);
+
+    let feb = &deltas[1];
+    assert_eq!(feb.count(BaselineStatus::Missing), 2);
+    let a = feb.entries.iter().find(|e| e.normalized_rel_path == "a.txt").unwrap();
+    assert_eq!(a.delta_percent(), Some(-25.0));
+}
+
+#[test]
+fn test_parse_snapshot_date() {
+    assert_eq!(parse_snapshot_date("2024-12-15"), Ok(NaiveDate::from_ymd_opt(2024, 12, 15).unwrap()));
+    assert_eq!(parse_snapshot_date("2024-12"), Ok(NaiveDate::from_ymd_opt(2024, 12, 1).unwrap()));
+    assert_eq!(parse_snapshot_date(" 2024-12 "), Ok(NaiveDate::from_ymd_opt(2024, 12, 1).unwrap()));
+    assert!(parse_snapshot_date("2024-12-32").is_err());
+    assert!(parse_snapshot_date("2024-13").is_err());
+    assert!(parse_snapshot_date("Dec 2024").is_err());
+}
+
+#[test]
+fn test_granularity_label() {
+    let date = NaiveDate::from_ymd_opt(2024, 12, 30).unwrap();
+    assert_eq!(Granularity::Daily.label(date), "2024-12-30");
+    // ISO week 1 of 2025 starts on Monday 2024-12-30
+    assert_eq!(Granularity::Weekly.label(date), "2025-W01");
+    assert_eq!(Granularity::Monthly.label(date), "2024-12");
+    assert_eq!("Weekly".parse::<Granularity>().unwrap(), Granularity::Weekly);
+    assert!("hourly".parse::<Granularity>().is_err());
+}
+
+#[test]
+fn test_date_range() {
+    let ymd = |y, m, d| NaiveDate::from_ymd_opt(y, m, d).unwrap();
+    let months = date_range(ymd(2024, 4, 15), ymd(2025, 3, 1), Granularity::Monthly);
+    assert_eq!(months.len(), 12);
+    assert_eq!(months[0], ymd(2024, 4, 1));
+    assert_eq!(months[11], ymd(2025, 3, 1));
+
+    assert_eq!(
+        date_range(ymd(2024, 7, 1), ymd(2024, 7, 20), Granularity::Weekly),
+        vec![ymd(2024, 7, 1), ymd(2024, 7, 8), ymd(2024, 7, 15)]
+    );
+    assert_eq!(date_range(ymd(2024, 2, 28), ymd(2024, 3, 1), Granularity::Daily).len(), 3);
+    assert!(date_range(ymd(2025, 1, 1), ymd(2024, 1, 1), Granularity::Monthly).is_empty());
+}
+
+#[test]
+fn test_last_periods() {
+    let ymd = |y, m, d| NaiveDate::from_ymd_opt(y, m, d).unwrap();
+    let months = last_periods(ymd(2025, 3, 10), 12, Granularity::Monthly);
+    assert_eq!(months.first(), Some(&ymd(2024, 4, 1)));
+    assert_eq!(months.last(), Some(&ymd(2025, 3, 1)));
+    assert_eq!(months.len(), 12);
+    assert_eq!(last_periods(ymd(2024, 7, 15), 3, Granularity::Weekly), vec![ymd(2024, 7, 1), ymd(2024, 7, 8), ymd(2024, 7, 15)]);
+    assert!(last_periods(ymd(2024, 7, 15), 0, Granularity::Daily).is_empty());
+}
+
+#[test]
+fn test_fiscal_year_quarter() {
+    let ymd = |y, m| NaiveDate::from_ymd_opt(y, m, 1).unwrap();
+    assert_eq!(fiscal_year_quarter(ymd(2024, 4), 4), (2024, 1));
+    assert_eq!(fiscal_year_quarter(ymd(2024, 12), 4), (2024, 3));
+    assert_eq!(fiscal_year_quarter(ymd(2025, 3), 4), (2024, 4));
+    assert_eq!(fiscal_year_quarter(ymd(2025, 3), 1), (2025, 1));
+    assert_eq!(fiscal_year_quarter(ymd(2024, 9), 10), (2023, 4));
+
+    assert_eq!(fiscal_quarter_start(2024, 4, 4), Some(ymd(2025, 1)));
+    assert_eq!(fiscal_quarter_start(2023, 4, 10), Some(ymd(2024, 7)));
+    assert_eq!(fiscal_quarter_start(2024, 0, 4), None);
+
+    assert_eq!(
+        resolve_template("/fin/FY{fy}/Q{q}/{yyyy}{mm}", ymd(2025, 2)),
+        PathBuf::from("/fin/FY2024/Q4/202502")
+    );
+}
+
+#[test]
+fn test_rollup() {
+    let ymd = |y, m| NaiveDate::from_ymd_opt(y, m, 1).unwrap();
+    assert_eq!(Rollup::Quarter.label(ymd(2025, 2), 4), "FY2024-Q4");
+    assert_eq!(Rollup::FiscalYear.label(ymd(2025, 2), 4), "FY2024");
+    assert_eq!("fiscal-year".parse::<Rollup>().unwrap(), Rollup::FiscalYear);
+
+    let months: Vec<NaiveDate> = (1..=7).map(|m| ymd(2024, m)).collect();
+    // Latest month of each quarter: 2024-03 (FY2023 Q4), 2024-06 (Q1), 2024-07 (Q2 so far)
+    assert_eq!(roll_up_dates(&months, Rollup::Quarter, 4), vec![ymd(2024, 3), ymd(2024, 6), ymd(2024, 7)]);
+    assert_eq!(roll_up_dates(&months, Rollup::FiscalYear, 4), vec![ymd(2024, 3), ymd(2024, 7)]);
+}
+
+#[test]
+fn test_normalize_filename_with_day() {
+    let with_day = |name: &str, yyyy, mm, dd| {
+        let date = NaiveDate::from_ymd_opt(yyyy, mm, dd).unwrap();
+        DateNormalizer::new(date, Granularity::Daily, 0).normalize(name).normalized
+    };
+    assert_eq!(with_day("log_20240715.txt", 2024, 7, 15), "log_{yyyy}{mm}{dd}.txt");
+    // month and day equal: the month-day pair is matched first
+    assert_eq!(with_day("log_2024-07-07.txt", 2024, 7, 7), "log_{yyyy}-{mm}-{dd}.txt");
+    assert_eq!(with_day("day15_07.txt", 2024, 7, 15), "day{dd}_{mm}.txt");
+
+    let date = NaiveDate::from_ymd_opt(2024, 7, 15).unwrap();
+    let rel_path = |g| DateNormalizer::new(date, g, 0).normalize_rel_path("Sub/log_20240715.txt", &[]).normalized;
+    assert_eq!(rel_path(Granularity::Daily), "Sub/log_{yyyy}{mm}{dd}.txt");
+    assert_eq!(rel_path(Granularity::Monthly), "Sub/log_{yyyy}{mm}15.txt");
+}
+
+#[test]
+fn test_decode_text() {
+    assert_eq!(decode_text("データ\n".as_bytes()), "データ\n");
+    // UTF-8 BOM is stripped
+    assert_eq!(decode_text(b"\xEF\xBB\xBFabc"), "abc");
+    // Not valid UTF-8 -> Shift_JIS ("データ")
+    assert_eq!(decode_text(b"\x83\x66\x81\x5B\x83\x5E"), "データ");
+    // UTF-16 without a BOM, told apart by the NUL bytes of ASCII characters
+    let le: Vec<u8> = "氏名,1\n".encode_utf16().flat_map(|u| u.to_le_bytes()).collect();
+    assert_eq!(decode_text(&le), "氏名,1\n");
+    let be: Vec<u8> = "氏名,1\n".encode_utf16().flat_map(|u| u.to_be_bytes()).collect();
+    assert_eq!(decode_text(&be), "氏名,1\n");
+}
+
+#[test]
+fn test_text_diff_counts_lines() {
+    let (added, removed, unified) = text_diff("a\nb\nc\n", "a\nB\nc\nd\n", "old.txt", "new.txt");
+    assert_eq!(added, 2);
+    assert_eq!(removed, 1);
+    assert!(unified.starts_with("--- old.txt\n+++ new.txt\n"));
+    assert!(unified.contains("-b\n+B\n"));
+
+    let (added, removed, unified) = text_diff("same\n", "same\n", "a", "b");
+    assert_eq!((added, removed), (0, 0));
+    assert!(unified.is_empty());
+}
+
+#[test]
+fn test_is_text_like() {
+    assert!(is_text_like("Sub/SubFile1_{mm}-{yyyy}.txt"));
+    assert!(is_text_like("data.CSV"));
+    assert!(is_text_like("server.log"));
+    assert!(!is_text_like("InTheBox{mm}-{yyyy}.xlsx"));
+    assert!(!is_text_like("README"));
+}
+
+#[test]
+fn test_parse_csv_schema() {
+    let schema = parse_csv_schema("id,name,\"note, with comma\"\n1,a,x\n2,b,\"multi\nline\"\n", b',');
+    assert_eq!(schema.columns, vec!["id", "name", "note, with comma"]);
+    assert_eq!(schema.row_count, 2);
+
+    let tsv = parse_csv_schema("a\tb\n1\t2\n", b'\t');
+    assert_eq!(tsv.columns, vec!["a", "b"]);
+    assert_eq!(tsv.row_count, 1);
+}
+
+#[test]
+fn test_diff_schemas() {
+    let schema = |cols: &[&str], rows: usize| CsvSchema {
+        columns: cols.iter().map(|c| c.to_string()).collect(),
+        row_count: rows,
+    };
+    let kinds = |prev: &CsvSchema, curr: &CsvSchema| -> Vec<SchemaDriftKind> {
+        diff_schemas(prev, curr, 50.0).iter().map(|d| d.kind).collect()
+    };
+
+    let base = schema(&["id", "name", "amount"], 100);
+    assert!(kinds(&base, &schema(&["id", "name", "amount"], 140)).is_empty());
+    assert_eq!(kinds(&base, &schema(&["id", "name", "amount"], 150)), vec![SchemaDriftKind::RowCountJump]);
+    assert_eq!(kinds(&base, &schema(&["id", "name", "amount"], 40)), vec![SchemaDriftKind::RowCountJump]);
+    assert_eq!(
+        kinds(&base, &schema(&["id", "amount", "name"], 100)),
+        vec![SchemaDriftKind::ColumnsReordered]
+    );
+
+    let drifts = diff_schemas(&base, &schema(&["id", "name", "tax"], 100), 50.0);
+    assert_eq!(drifts.len(), 2);
+    assert_eq!(drifts[0].kind, SchemaDriftKind::ColumnAdded);
+    assert_eq!(drifts[0].column.as_deref(), Some("tax"));
+    assert_eq!(drifts[1].kind, SchemaDriftKind::ColumnRemoved);
+    assert_eq!(drifts[1].column.as_deref(), Some("amount"));
+
+    // Growth from an empty file is always a jump
+    assert_eq!(kinds(&schema(&["id"], 0), &schema(&["id"], 1)), vec![SchemaDriftKind::RowCountJump]);
+}
+
+#[test]
+fn test_detect_size_anomalies_percent() {
+    let series: Vec<FileInfo> = [("2024-10", 100), ("2024-11", 140), ("2024-12", 210), ("2025-01", 0), ("2025-02", 50)]
+        .iter()
+        .map(|(date, size)| file_info("a.txt", date, *size, "2024/12/01 09:00"))
+        .collect();
+
+    let anomalies = detect_size_anomalies(&series, &AnomalyThresholds::default());
+    // 100 -> 140 (+40%) stays below 50%; 0 -> 50 has no relative change
+    assert_eq!(anomalies.len(), 2);
+    assert_eq!(anomalies[0].date_str, "2024-12");
+    assert_eq!(anomalies[0].kind, AnomalyKind::SizeJump);
+    assert_eq!(anomalies[0].severity, Severity::Warning);
+    assert_eq!(anomalies[0].delta_percent(), Some(50.0));
+    assert_eq!(anomalies[1].date_str, "2025-01");
+    assert_eq!(anomalies[1].kind, AnomalyKind::ZeroBytes);
+    assert_eq!(anomalies[1].severity, Severity::Critical);
+}
+
+#[test]
+fn test_detect_size_anomalies_bytes() {
+    // Out of order on purpose: the series is sorted by date first
+    let series = vec![
+        file_info("big.bin", "2025-01", 10_000, "2025/01/01 09:00"),
+        file_info("big.bin", "2024-12", 1_000_000, "2024/12/01 09:00"),
+        file_info("big.bin", "2025-02", 13_000, "2025/02/01 09:00"),
+    ];
+    let thresholds = AnomalyThresholds {
+        percent: None,
+        bytes: Some(1_000),
+    };
+
+    let anomalies = detect_size_anomalies(&series, &thresholds);
+    assert_eq!(anomalies.len(), 2);
+    assert_eq!(anomalies[0].kind, AnomalyKind::SizeDrop);
+    assert_eq!(anomalies[0].severity, Severity::Critical);
+    assert_eq!(anomalies[1].kind, AnomalyKind::SizeJump);
+    assert_eq!(anomalies[1].prev_size, 10_000);
+}
+
+#[test]
+fn test_presence_matrix_gaps() {
+    let snap = |date: &str, files: Vec<FileInfo>| Snapshot {
+        date_str: date.to_string(),
+        root: PathBuf::new(),
+        files,
+    };
+    let snapshots = vec![
+        snap("2024-09", vec![file_info("b.txt", "2024-09", 1, "m"), file_info("a.txt", "2024-09", 1, "m")]),
+        snap("2024-07", vec![file_info("a.txt", "2024-07", 1, "m"), file_info("b.txt", "2024-07", 1, "m")]),
+        snap("2024-08", vec![file_info("a.txt", "2024-08", 1, "m")]),
+    ];
+
+    let matrix = presence_matrix(&snapshots);
+    assert_eq!(matrix.months, vec!["2024-07", "2024-08", "2024-09"]);
+    assert_eq!(matrix.rows.len(), 2);
+    assert_eq!(matrix.rows[0].normalized_rel_path, "a.txt");
+    assert_eq!(matrix.rows[0].present, vec![true, true, true]);
+    assert!(matrix.rows[0].missing_months.is_empty());
+    assert_eq!(matrix.rows[1].present, vec![true, false, true]);
+    assert_eq!(matrix.rows[1].missing_months, vec!["2024-08"]);
+
+    let gaps: Vec<&str> = matrix.gaps().map(|g| g.normalized_rel_path.as_str()).collect();
+    assert_eq!(gaps, vec!["b.txt"]);
+}
+
+#[test]
+fn test_hash_algorithm_from_str() {
+    assert_eq!("sha256".parse::<HashAlgorithm>().unwrap(), HashAlgorithm::Sha256);
+    assert_eq!("XXH3".parse::<HashAlgorithm>().unwrap(), HashAlgorithm::Xxh3);
+    assert!("md5".parse::<HashAlgorithm>().is_err());
+}
+
 #[cfg(test)]
 mod date_parsing_tests {
     use super::*;
@@ -140,4 +897,4 @@ mod date_parsing_tests {
         let invalid_date2 = NaiveDate::from_ymd_opt(2024, 2, 30);
         assert!(invalid_date2.is_none());
     }
-}
\ No newline at end of file
+}